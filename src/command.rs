@@ -0,0 +1,40 @@
+/// The shell command an item holds: everything after a leading `$ `, or else the first part of
+/// the text in `backticks`.
+pub fn item_command(text: &str) -> Option<String> {
+    let command = match text.trim_start().strip_prefix("$ ") {
+        Some(command) => command,
+        None => {
+            let (start, end) = backtick_spans(text).into_iter().next()?;
+            &text[start + 1..end - 1]
+        }
+    };
+    let command = command.trim();
+    (!command.is_empty()).then(|| command.to_string())
+}
+
+/// Byte ranges of the parts of a displayed line that mark a command, for highlighting: the `$`
+/// of a leading `$ ` and every `backtick` span including its backticks.
+pub fn command_spans(line: &str) -> Vec<(usize, usize)> {
+    let mut spans = backtick_spans(line);
+    let indent = line.len() - line.trim_start().len();
+    if line[indent..].starts_with("$ ") {
+        spans.insert(0, (indent, indent + 1));
+    }
+    spans
+}
+
+fn backtick_spans(text: &str) -> Vec<(usize, usize)> {
+    // Pairs of backticks with something between them; a lone backtick is left alone
+    let mut spans = Vec::new();
+    let mut rest_start = 0;
+    while let Some(open) = text[rest_start..].find('`').map(|offset| rest_start + offset) {
+        let Some(close) = text[open + 1..].find('`').map(|offset| open + 1 + offset) else {
+            break;
+        };
+        if close > open + 1 {
+            spans.push((open, close + 1));
+        }
+        rest_start = close + 1;
+    }
+    spans
+}
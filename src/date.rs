@@ -0,0 +1,233 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+
+// Years that fit the four digits of `YYYY-MM-DD`; anything outside is no date
+const MIN_YEAR: i64 = 0;
+const MAX_YEAR: i64 = 9999;
+
+/// A calendar date without time zone, stored on disk as `YYYY-MM-DD`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Date {
+    // Days since 1970-01-01
+    days: i64,
+}
+
+impl Date {
+    pub fn from_ymd(year: i64, month: u32, day: u32) -> Option<Date> {
+        if !(MIN_YEAR..=MAX_YEAR).contains(&year) || !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month) {
+            return None;
+        }
+
+        // Days-from-civil conversion, see http://howardhinnant.github.io/date_algorithms.html
+        let year = if month <= 2 { year - 1 } else { year };
+        let era = year.div_euclid(400);
+        let year_of_era = year - era * 400;
+        let month = month as i64;
+        let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+        Some(Date {
+            days: era * 146097 + day_of_era - 719468,
+        })
+    }
+
+    pub fn ymd(self) -> (i64, u32, u32) {
+        // Civil-from-days conversion, the inverse of from_ymd
+        let days = self.days + 719468;
+        let era = days.div_euclid(146097);
+        let day_of_era = days - era * 146097;
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153;
+        let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+        let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 } as u32;
+        let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        (year, month, day)
+    }
+
+    pub fn parse_iso(text: &str) -> Option<Date> {
+        let mut parts = text.splitn(3, '-');
+        let year = parts.next()?.parse().ok()?;
+        let month = parts.next()?.parse().ok()?;
+        let day = parts.next()?.parse().ok()?;
+        Date::from_ymd(year, month, day)
+    }
+
+    /// Day of the week, 0 for Monday through 6 for Sunday.
+    pub fn weekday(self) -> u32 {
+        // 1970-01-01 was a Thursday
+        (self.days + 3).rem_euclid(7) as u32
+    }
+
+    /// The date `days` later (or earlier), if it is still within years 0 to 9999.
+    pub fn add_days(self, days: i64) -> Option<Date> {
+        let date = Date {
+            days: self.days.checked_add(days)?,
+        };
+        let (first, last) = (Date::from_ymd(MIN_YEAR, 1, 1)?, Date::from_ymd(MAX_YEAR, 12, 31)?);
+        (first..=last).contains(&date).then_some(date)
+    }
+
+    /// Number of days from `self` until `other`, negative if `other` is earlier.
+    pub fn days_until(self, other: Date) -> i64 {
+        other.days - self.days
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (year, month, day) = self.ymd();
+        write!(f, "{:04}-{:02}-{:02}", year, month, day)
+    }
+}
+
+impl Serialize for Date {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Date {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Date, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        Date::parse_iso(&text).ok_or_else(|| serde::de::Error::custom(format!("invalid date: {}", text)))
+    }
+}
+
+/// Parses the value of a `due:` token: an ISO date, `today`, `tomorrow`, a weekday name
+/// (the next such day after today) or an offset such as `3d` or `2w`. Everything except
+/// ISO dates needs to know today's date.
+pub fn parse_due(value: &str, today: Option<Date>) -> Option<Date> {
+    if let Some(date) = Date::parse_iso(value) {
+        return Some(date);
+    }
+
+    let today = today?;
+    let value = value.to_lowercase();
+
+    match value.as_str() {
+        "today" | "tod" => return Some(today),
+        "tomorrow" | "tom" | "tmr" => return today.add_days(1),
+        _ => {}
+    }
+
+    if let Some(weekday) = parse_weekday(&value) {
+        let days_ahead = (weekday as i64 - today.weekday() as i64 - 1).rem_euclid(7) + 1;
+        return today.add_days(days_ahead);
+    }
+
+    // The unit is the last character, which isn't necessarily one byte long
+    let (unit_start, unit) = value.char_indices().last()?;
+    let amount: i64 = value[..unit_start].parse().ok()?;
+    match unit {
+        'd' => today.add_days(amount),
+        'w' => today.add_days(amount.checked_mul(7)?),
+        _ => None,
+    }
+}
+
+/// Short label for a due date relative to today, e.g. "today", "in 2d" or "overdue 3d".
+pub fn relative_label(due: Date, today: Date) -> String {
+    match today.days_until(due) {
+        0 => "today".to_string(),
+        1 => "tomorrow".to_string(),
+        days if days > 0 => format!("in {}d", days),
+        days => format!("overdue {}d", -days),
+    }
+}
+
+fn parse_weekday(name: &str) -> Option<u32> {
+    match name {
+        "mon" | "monday" => Some(0),
+        "tue" | "tues" | "tuesday" => Some(1),
+        "wed" | "wednesday" => Some(2),
+        "thu" | "thur" | "thurs" | "thursday" => Some(3),
+        "fri" | "friday" => Some(4),
+        "sat" | "saturday" => Some(5),
+        "sun" | "sunday" => Some(6),
+        _ => None,
+    }
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        4 | 6 | 9 | 11 => 30,
+        2 if (year % 4 == 0 && year % 100 != 0) || year % 400 == 0 => 29,
+        2 => 28,
+        _ => 31,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i64, month: u32, day: u32) -> Date {
+        Date::from_ymd(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn parses_iso_dates_without_today() {
+        assert_eq!(parse_due("2024-02-29", None), Some(date(2024, 2, 29)));
+        assert_eq!(parse_due("2023-02-29", None), None);
+        assert_eq!(parse_due("tomorrow", None), None);
+    }
+
+    #[test]
+    fn parses_relative_dates() {
+        // 2024-01-01 was a Monday
+        let today = Some(date(2024, 1, 1));
+        assert_eq!(parse_due("today", today), Some(date(2024, 1, 1)));
+        assert_eq!(parse_due("Tomorrow", today), Some(date(2024, 1, 2)));
+        assert_eq!(parse_due("fri", today), Some(date(2024, 1, 5)));
+        // The same weekday means next week, not today
+        assert_eq!(parse_due("monday", today), Some(date(2024, 1, 8)));
+        assert_eq!(parse_due("3d", today), Some(date(2024, 1, 4)));
+        assert_eq!(parse_due("2w", today), Some(date(2024, 1, 15)));
+        assert_eq!(parse_due("-1d", today), Some(date(2023, 12, 31)));
+    }
+
+    #[test]
+    fn rejects_other_units() {
+        let today = Some(date(2024, 1, 1));
+        assert_eq!(parse_due("", today), None);
+        assert_eq!(parse_due("d", today), None);
+        assert_eq!(parse_due("3m", today), None);
+        assert_eq!(parse_due("café", today), None);
+        assert_eq!(parse_due("3é", today), None);
+        assert_eq!(parse_due("3日", today), None);
+    }
+
+    #[test]
+    fn rejects_dates_out_of_range() {
+        let today = Some(date(2024, 1, 1));
+        assert_eq!(parse_due("9223372036854775807d", today), None);
+        assert_eq!(parse_due("-9223372036854775808d", today), None);
+        assert_eq!(parse_due("2000000000000000000w", today), None);
+        assert_eq!(parse_due("3000000d", today), None);
+        assert_eq!(parse_due("9223372036854000000d", today), None);
+        assert_eq!(parse_due("9223372036854775807-01-01", today), None);
+        assert_eq!(date(9999, 12, 31).add_days(1), None);
+        assert_eq!(date(0, 1, 1).add_days(-1), None);
+        assert_eq!(date(9999, 12, 30).add_days(1), Some(date(9999, 12, 31)));
+    }
+
+    #[test]
+    fn labels_relative_to_today() {
+        let today = date(2024, 1, 1);
+        assert_eq!(relative_label(today, today), "today");
+        assert_eq!(relative_label(date(2024, 1, 2), today), "tomorrow");
+        assert_eq!(relative_label(date(2024, 1, 11), today), "in 10d");
+        assert_eq!(relative_label(date(2023, 12, 29), today), "overdue 3d");
+    }
+
+    #[test]
+    fn converts_to_and_from_ymd() {
+        for (year, month, day) in [(1970, 1, 1), (2000, 2, 29), (2024, 12, 31), (1969, 12, 31)] {
+            assert_eq!(date(year, month, day).ymd(), (year, month, day));
+        }
+        assert_eq!(date(1970, 1, 1).weekday(), 3);
+        assert_eq!(date(2024, 3, 1).to_string(), "2024-03-01");
+    }
+}
@@ -0,0 +1,456 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use zellij_tile::prelude::*;
+
+/// Where a key is pressed. Every context has its own bindings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Context {
+    Normal,
+    // Normal mode while an item is grabbed; keys not bound here fall through to Normal
+    Grab,
+    Edit,
+    Search,
+    Lists,
+    ListName,
+    Details,
+    Notes,
+    References,
+    CodeComments,
+    LoadError,
+    Help,
+}
+
+impl Context {
+    pub fn title(self) -> &'static str {
+        match self {
+            Context::Normal => "Normal mode",
+            Context::Grab => "Grab mode",
+            Context::Edit => "Edit mode",
+            Context::Search => "Search",
+            Context::Lists => "List picker",
+            Context::ListName => "List name",
+            Context::Details => "Item details",
+            Context::Notes => "Notes editor",
+            Context::References => "Open reference",
+            Context::CodeComments => "Code TODOs",
+            Context::LoadError => "Read-only",
+            Context::Help => "Help",
+        }
+    }
+}
+
+// Contexts whose bindings can be changed with `bind_<action>` options
+const CONFIGURABLE: [Context; 2] = [Context::Normal, Context::Grab];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Up,
+    Down,
+    Collapse,
+    Expand,
+    Indent,
+    Outdent,
+    ToggleDone,
+    RaisePriority,
+    LowerPriority,
+    NextTag,
+    ClearTag,
+    SortByPriority,
+    Wrap,
+    Details,
+    RunCommand,
+    RunCommandTiled,
+    OpenReference,
+    ScanCode,
+    Promote,
+    NextList,
+    PrevList,
+    Lists,
+    MoveToList,
+    StartProjectFile,
+    Grab,
+    Add,
+    Edit,
+    Delete,
+    Undo,
+    Redo,
+    Search,
+    NextMatch,
+    PrevMatch,
+    Paste,
+    MoveUp,
+    MoveDown,
+    Release,
+    Save,
+    Cancel,
+    Pick,
+    NewList,
+    RenameList,
+    DeleteList,
+    EditNotes,
+    Retry,
+    UseBackup,
+    PageUp,
+    PageDown,
+    Help,
+    Back,
+    Quit,
+}
+
+impl Action {
+    /// Whether the action works on the selected item, so does nothing while there are no items.
+    pub fn needs_item(self) -> bool {
+        matches!(
+            self,
+            Action::Up
+                | Action::Down
+                | Action::Collapse
+                | Action::Expand
+                | Action::Indent
+                | Action::Outdent
+                | Action::ToggleDone
+                | Action::RaisePriority
+                | Action::LowerPriority
+                | Action::NextTag
+                | Action::ClearTag
+                | Action::Details
+                | Action::RunCommand
+                | Action::RunCommandTiled
+                | Action::OpenReference
+                | Action::MoveToList
+                | Action::Grab
+                | Action::Edit
+                | Action::Delete
+                | Action::Search
+                | Action::NextMatch
+                | Action::PrevMatch
+        )
+    }
+
+    /// Name used in `bind_<name>` configuration options.
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Up => "up",
+            Action::Down => "down",
+            Action::Collapse => "collapse",
+            Action::Expand => "expand",
+            Action::Indent => "indent",
+            Action::Outdent => "outdent",
+            Action::ToggleDone => "toggle",
+            Action::RaisePriority => "raise_priority",
+            Action::LowerPriority => "lower_priority",
+            Action::NextTag => "next_tag",
+            Action::ClearTag => "clear_tag",
+            Action::SortByPriority => "sort",
+            Action::Wrap => "wrap",
+            Action::Details => "details",
+            Action::RunCommand => "run_command",
+            Action::RunCommandTiled => "run_command_tiled",
+            Action::OpenReference => "open_reference",
+            Action::ScanCode => "scan_code",
+            Action::Promote => "promote",
+            Action::NextList => "next_list",
+            Action::PrevList => "prev_list",
+            Action::Lists => "lists",
+            Action::MoveToList => "move_to_list",
+            Action::StartProjectFile => "start_project_file",
+            Action::Grab => "grab",
+            Action::Add => "add",
+            Action::Edit => "edit",
+            Action::Delete => "delete",
+            Action::Undo => "undo",
+            Action::Redo => "redo",
+            Action::Search => "search",
+            Action::NextMatch => "next_match",
+            Action::PrevMatch => "prev_match",
+            Action::Paste => "paste",
+            Action::MoveUp => "move_up",
+            Action::MoveDown => "move_down",
+            Action::Release => "release",
+            Action::Save => "save",
+            Action::Cancel => "cancel",
+            Action::Pick => "pick",
+            Action::NewList => "new_list",
+            Action::RenameList => "rename_list",
+            Action::DeleteList => "delete_list",
+            Action::EditNotes => "edit_notes",
+            Action::Retry => "retry",
+            Action::UseBackup => "use_backup",
+            Action::PageUp => "page_up",
+            Action::PageDown => "page_down",
+            Action::Help => "help",
+            Action::Back => "back",
+            Action::Quit => "close",
+        }
+    }
+}
+
+pub struct Binding {
+    pub context: Context,
+    pub action: Action,
+    pub keys: Vec<KeyWithModifier>,
+    pub description: &'static str,
+    // Short label for the status bar, for the bindings worth showing there
+    pub hint: Option<&'static str>,
+    // Set by a `bind_<action>` option rather than the defaults
+    pub configured: bool,
+}
+
+// Context, action, keys in the `zellij` KDL notation, description for the help overlay and
+// status bar hint
+type DefaultBinding = (Context, Action, &'static [&'static str], &'static str, Option<&'static str>);
+
+// Within a context, bindings are listed in the order the help shows them
+const DEFAULT_BINDINGS: &[DefaultBinding] = &[
+    (Context::Normal, Action::Add, &["a"], "Add new item above", Some("add")),
+    (Context::Normal, Action::Edit, &["Enter"], "Edit item", Some("edit")),
+    (Context::Normal, Action::ToggleDone, &["Space"], "Toggle todo / done", Some("done")),
+    (Context::Normal, Action::Delete, &["Delete", "Backspace"], "Delete item and its subtasks", None),
+    (Context::Normal, Action::Grab, &["g"], "Grab item for reordering", Some("grab")),
+    (Context::Normal, Action::Up, &["Up", "k"], "Move up", None),
+    (Context::Normal, Action::Down, &["Down", "j"], "Move down", None),
+    (Context::Normal, Action::Collapse, &["Left", "h"], "Collapse item (or jump to its parent)", None),
+    (Context::Normal, Action::Expand, &["Right", "l"], "Expand collapsed item", None),
+    (Context::Normal, Action::Indent, &["Tab"], "Indent under the item above", None),
+    (Context::Normal, Action::Outdent, &["Shift Tab"], "Outdent to the parent's level", None),
+    (Context::Normal, Action::RaisePriority, &["+", "="], "Raise priority", None),
+    (Context::Normal, Action::LowerPriority, &["-"], "Lower priority", None),
+    (Context::Normal, Action::NextTag, &["t"], "Filter by the next tag", None),
+    (Context::Normal, Action::ClearTag, &["T"], "Clear the tag filter", None),
+    (Context::Normal, Action::SortByPriority, &["s"], "Toggle grouping by priority", None),
+    (Context::Normal, Action::Wrap, &["w"], "Toggle wrapping of long items", None),
+    (Context::Normal, Action::Details, &["o"], "Show the item's notes", None),
+    (Context::Normal, Action::RunCommand, &["x"], "Run the item's command in a floating pane", None),
+    (Context::Normal, Action::RunCommandTiled, &["X"], "Run the item's command in a tiled pane", None),
+    (Context::Normal, Action::OpenReference, &["f"], "Open a file the item refers to", None),
+    (Context::Normal, Action::ScanCode, &["c"], "Show TODO, FIXME and HACK comments in the code", None),
+    (Context::Normal, Action::NextList, &["]"], "Switch to the next list", None),
+    (Context::Normal, Action::PrevList, &["["], "Switch to the previous list", None),
+    (Context::Normal, Action::Lists, &["L"], "Open the list picker", None),
+    (Context::Normal, Action::MoveToList, &["m"], "Move item to another list", None),
+    (Context::Normal, Action::StartProjectFile, &["P"], "Start a todo file at the git root", None),
+    (Context::Normal, Action::Undo, &["u"], "Undo last change", None),
+    (Context::Normal, Action::Redo, &["Ctrl r"], "Redo", None),
+    (Context::Normal, Action::Search, &["/"], "Search items", Some("search")),
+    (Context::Normal, Action::NextMatch, &["n"], "Jump to next search match", None),
+    (Context::Normal, Action::PrevMatch, &["N"], "Jump to previous search match", None),
+    (Context::Normal, Action::Paste, &["Ctrl v"], "Paste text (parses markdown lists)", None),
+    (Context::Normal, Action::Help, &["?"], "Show this help", Some("help")),
+    (Context::Normal, Action::Back, &["Esc"], "Clear search, or quit plugin", None),
+    (Context::Normal, Action::Quit, &["q"], "Quit plugin", Some("quit")),
+    (Context::Grab, Action::MoveUp, &["Up", "k"], "Move item up among its siblings", Some("up")),
+    (Context::Grab, Action::MoveDown, &["Down", "j"], "Move item down among its siblings", Some("down")),
+    (Context::Grab, Action::Grab, &["g"], "Release item", Some("release")),
+    (Context::Grab, Action::Release, &["Space", "Enter", "a", "Delete", "Backspace", "Esc"], "Release item", None),
+    (Context::Edit, Action::Save, &["Enter"], "Save changes", Some("save")),
+    (Context::Edit, Action::Cancel, &["Esc"], "Cancel changes", Some("cancel")),
+    (Context::Edit, Action::Paste, &["Ctrl v"], "Paste text at the cursor", None),
+    (Context::Edit, Action::Help, &["F1"], "Show this help", None),
+    (Context::Search, Action::NextMatch, &["Down"], "Next match", Some("next")),
+    (Context::Search, Action::PrevMatch, &["Up"], "Previous match", Some("previous")),
+    (Context::Search, Action::Save, &["Enter"], "Keep the query", Some("keep")),
+    (Context::Search, Action::Cancel, &["Esc"], "Clear the query and go back", Some("clear")),
+    (Context::Search, Action::Help, &["F1"], "Show this help", None),
+    (Context::Lists, Action::Up, &["Up", "k"], "Move up", None),
+    (Context::Lists, Action::Down, &["Down", "j"], "Move down", None),
+    (Context::Lists, Action::Pick, &["Enter"], "Switch to the list, or move the item there", None),
+    (Context::Lists, Action::NewList, &["n"], "Create a new list", None),
+    (Context::Lists, Action::RenameList, &["r"], "Rename the list", None),
+    (Context::Lists, Action::DeleteList, &["d"], "Delete the list (only when it is empty)", None),
+    (Context::Lists, Action::Help, &["?"], "Show this help", None),
+    (Context::Lists, Action::Back, &["Esc", "q"], "Back to the items", None),
+    (Context::ListName, Action::Save, &["Enter"], "Save the name", None),
+    (Context::ListName, Action::Cancel, &["Esc"], "Back to the picker", None),
+    (Context::ListName, Action::Help, &["F1"], "Show this help", None),
+    (Context::Details, Action::Up, &["Up", "k"], "Scroll up", None),
+    (Context::Details, Action::Down, &["Down", "j"], "Scroll down", None),
+    (Context::Details, Action::EditNotes, &["e", "Enter"], "Edit the notes", None),
+    (Context::Details, Action::Help, &["?"], "Show this help", None),
+    (Context::Details, Action::Back, &["Esc", "q", "o"], "Back to the items", None),
+    (Context::Notes, Action::Save, &["Esc"], "Save the notes", None),
+    (Context::Notes, Action::Paste, &["Ctrl v"], "Paste text at the cursor", None),
+    (Context::Notes, Action::Help, &["F1"], "Show this help", None),
+    (Context::References, Action::Up, &["Up", "k"], "Move up", None),
+    (Context::References, Action::Down, &["Down", "j"], "Move down", None),
+    (Context::References, Action::Pick, &["Enter"], "Open the file in a new pane", None),
+    (Context::References, Action::Help, &["?"], "Show this help", None),
+    (Context::References, Action::Back, &["Esc", "q"], "Back to the items", None),
+    (Context::CodeComments, Action::Up, &["Up", "k"], "Move up", None),
+    (Context::CodeComments, Action::Down, &["Down", "j"], "Move down", None),
+    (Context::CodeComments, Action::Pick, &["Enter"], "Open the comment's source in a new pane", None),
+    (Context::CodeComments, Action::Promote, &["p"], "Add the comment to the list as an item", None),
+    (Context::CodeComments, Action::Retry, &["r"], "Scan again", None),
+    (Context::CodeComments, Action::Help, &["?"], "Show this help", None),
+    (Context::CodeComments, Action::Back, &["Esc", "q", "c"], "Back to the items", None),
+    (Context::LoadError, Action::Up, &["Up", "k"], "Move up", None),
+    (Context::LoadError, Action::Down, &["Down", "j"], "Move down", None),
+    (Context::LoadError, Action::UseBackup, &["b"], "Move the broken file aside and continue", None),
+    (Context::LoadError, Action::Retry, &["r"], "Try loading the file again", None),
+    (Context::LoadError, Action::Help, &["?"], "Show this help", None),
+    (Context::LoadError, Action::Quit, &["q", "Esc"], "Quit plugin", None),
+    (Context::Help, Action::Up, &["Up", "k"], "Scroll up", None),
+    (Context::Help, Action::Down, &["Down", "j"], "Scroll down", None),
+    (Context::Help, Action::PageUp, &["PageUp"], "Scroll up a page", None),
+    (Context::Help, Action::PageDown, &["PageDown", "Space"], "Scroll down a page", None),
+    (Context::Help, Action::Back, &["?", "Esc", "q", "F1"], "Close the help", None),
+];
+
+/// The key bindings of every context, looked up by the key handlers and listed by the help.
+pub struct Keymap {
+    bindings: Vec<Binding>,
+}
+
+impl Default for Keymap {
+    fn default() -> Keymap {
+        let bindings = DEFAULT_BINDINGS
+            .iter()
+            .map(|&(context, action, keys, description, hint)| Binding {
+                context,
+                action,
+                keys: keys.iter().filter_map(|key| parse_key(key)).collect(),
+                description,
+                hint,
+                configured: false,
+            })
+            .collect();
+        Keymap { bindings }
+    }
+}
+
+impl Keymap {
+    /// Applies `bind_<action> "<keys>"` options from the plugin configuration to the Normal and
+    /// Grab mode bindings, returning a description of every problem found. Keys are written as in
+    /// Zellij's own config (`"x"`, `"Ctrl t"`), several separated by commas, or `"none"`.
+    pub fn configure(&mut self, configuration: &BTreeMap<String, String>) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        for (option, value) in configuration {
+            let name = match option.strip_prefix("bind_") {
+                Some(name) => name,
+                None => continue,
+            };
+            let targets: Vec<usize> = (0..self.bindings.len())
+                .filter(|&idx| {
+                    let binding = &self.bindings[idx];
+                    CONFIGURABLE.contains(&binding.context) && binding.action.name() == name
+                })
+                .collect();
+            if targets.is_empty() {
+                problems.push(format!("unknown action in {}", option));
+                continue;
+            }
+
+            let mut keys = Vec::new();
+            for key in value.split(',').map(str::trim).filter(|key| !key.is_empty()) {
+                if key.eq_ignore_ascii_case("none") {
+                    continue;
+                }
+                match parse_key(key) {
+                    Some(key) => keys.push(key),
+                    None => problems.push(format!("unknown key \"{}\" in {}", key, option)),
+                }
+            }
+            for idx in targets {
+                self.bindings[idx].keys = keys.clone();
+                self.bindings[idx].configured = true;
+            }
+        }
+
+        // A key does one thing per context: configured bindings take it from the defaults,
+        // otherwise the binding listed first keeps it
+        for context in CONFIGURABLE {
+            let mut order: Vec<usize> = (0..self.bindings.len())
+                .filter(|&idx| self.bindings[idx].context == context)
+                .collect();
+            order.sort_by_key(|&idx| !self.bindings[idx].configured);
+
+            let mut owners: Vec<(KeyWithModifier, Action)> = Vec::new();
+            for idx in order {
+                let action = self.bindings[idx].action;
+                let mut keys = std::mem::take(&mut self.bindings[idx].keys);
+                keys.retain(|key| match owners.iter().find(|(owned, _)| owned == key) {
+                    Some((_, owner)) if *owner != action => {
+                        problems.push(format!(
+                            "{} is bound to both {} and {}, keeping {}",
+                            key_label(key),
+                            owner.name(),
+                            action.name(),
+                            owner.name()
+                        ));
+                        false
+                    }
+                    _ => {
+                        owners.push((key.clone(), action));
+                        true
+                    }
+                });
+                self.bindings[idx].keys = keys;
+            }
+        }
+
+        problems
+    }
+
+    pub fn action(&self, context: Context, key: &KeyWithModifier) -> Option<Action> {
+        let action = self
+            .bindings(context)
+            .find(|binding| binding.keys.contains(key))
+            .map(|binding| binding.action);
+        match action {
+            None if context == Context::Grab => self.action(Context::Normal, key),
+            _ => action,
+        }
+    }
+
+    pub fn bindings(&self, context: Context) -> impl Iterator<Item = &Binding> {
+        self.bindings.iter().filter(move |binding| binding.context == context)
+    }
+
+    /// Label of the first key bound to `action`, for hints elsewhere on screen that name it.
+    pub fn first_key_label(&self, context: Context, action: Action) -> Option<String> {
+        self.bindings(context).find(|binding| binding.action == action)?.keys.first().map(key_label)
+    }
+
+    /// `key: hint` labels for the status bar, most important first.
+    pub fn hints(&self, context: Context) -> Vec<(Action, String)> {
+        self.bindings(context)
+            .filter_map(|binding| {
+                Some((binding.action, format!("{}: {}", key_label(binding.keys.first()?), binding.hint?)))
+            })
+            .collect()
+    }
+}
+
+fn parse_key(key: &str) -> Option<KeyWithModifier> {
+    // A literal comma separates keys, so it has to be spelled out
+    let key = match key.rsplit_once(' ') {
+        Some((modifiers, bare_key)) if bare_key.eq_ignore_ascii_case("comma") => format!("{} ,", modifiers),
+        None if key.eq_ignore_ascii_case("comma") => ",".to_string(),
+        _ => key.to_string(),
+    };
+    KeyWithModifier::from_str(&key).ok()
+}
+
+/// How a key is written in the help and the status bar, e.g. `↑`, `Space` or `Ctrl+R`.
+pub fn key_label(key: &KeyWithModifier) -> String {
+    let bare_key = match key.bare_key {
+        BareKey::Up => "↑".to_string(),
+        BareKey::Down => "↓".to_string(),
+        BareKey::Left => "←".to_string(),
+        BareKey::Right => "→".to_string(),
+        BareKey::PageUp => "PgUp".to_string(),
+        BareKey::PageDown => "PgDn".to_string(),
+        BareKey::Char(' ') => "Space".to_string(),
+        BareKey::Char(c) if !key.key_modifiers.is_empty() => c.to_uppercase().to_string(),
+        BareKey::Char(c) => c.to_string(),
+        BareKey::F(index) => format!("F{}", index),
+        other => format!("{:?}", other),
+    };
+    let mut label = String::new();
+    for modifier in &key.key_modifiers {
+        label.push_str(&format!("{:?}+", modifier));
+    }
+    label.push_str(&bare_key);
+    label
+}
@@ -0,0 +1,278 @@
+use unicode_segmentation::UnicodeSegmentation;
+use zellij_tile::prelude::*;
+
+use crate::text;
+
+/// The keys `LineEditor::handle_key` understands besides typing, as listed in the help.
+pub const KEYS: &[(&str, &str)] = &[
+    ("← / →", "Move the cursor"),
+    ("Home / End, Ctrl+A / Ctrl+E", "Jump to start/end of line"),
+    ("Ctrl+← / Ctrl+→, Alt+B / Alt+F", "Jump a word back/forward"),
+    ("Backspace / Delete", "Delete before/under the cursor"),
+    ("Ctrl+W", "Delete the word before the cursor"),
+    ("Ctrl+U / Ctrl+K", "Delete to start/end of line"),
+];
+
+/// A single line of text with a cursor, as edited in Edit mode and when naming lists.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LineEditor {
+    text: String,
+    // Byte offset into `text`, always on a grapheme boundary
+    cursor: usize,
+}
+
+impl LineEditor {
+    /// Starts editing `text` with the cursor at the end.
+    pub fn new(text: String) -> LineEditor {
+        let cursor = text.len();
+        LineEditor { text, cursor }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.text.clear();
+        self.cursor = 0;
+    }
+
+    /// Handles an editing or cursor key, returning false for keys that aren't ours.
+    pub fn handle_key(&mut self, key: &KeyWithModifier) -> bool {
+        let ctrl = key.has_modifiers(&[KeyModifier::Ctrl]);
+        let alt = key.has_modifiers(&[KeyModifier::Alt]);
+
+        match key.bare_key {
+            BareKey::Left if key.has_no_modifiers() => self.cursor = self.prev_boundary(self.cursor),
+            BareKey::Right if key.has_no_modifiers() => self.cursor = self.next_boundary(self.cursor),
+            BareKey::Home if key.has_no_modifiers() => self.cursor = 0,
+            BareKey::End if key.has_no_modifiers() => self.cursor = self.text.len(),
+            BareKey::Char('a') if ctrl => self.cursor = 0,
+            BareKey::Char('e') if ctrl => self.cursor = self.text.len(),
+
+            // Word motions
+            BareKey::Left if ctrl => self.cursor = self.word_start_before(self.cursor),
+            BareKey::Right if ctrl => self.cursor = self.word_end_after(self.cursor),
+            BareKey::Char('b') if alt => self.cursor = self.word_start_before(self.cursor),
+            BareKey::Char('f') if alt => self.cursor = self.word_end_after(self.cursor),
+
+            BareKey::Backspace if key.has_no_modifiers() => {
+                let start = self.prev_boundary(self.cursor);
+                self.delete_range(start, self.cursor);
+            }
+            BareKey::Delete if key.has_no_modifiers() => {
+                let end = self.next_boundary(self.cursor);
+                self.delete_range(self.cursor, end);
+            }
+
+            // Readline-style kills: previous whitespace-separated word, to start, to end
+            BareKey::Char('w') if ctrl => {
+                let before = &self.text[..self.cursor];
+                let start = before
+                    .trim_end()
+                    .char_indices()
+                    .rev()
+                    .find(|(_, c)| c.is_whitespace())
+                    .map(|(idx, c)| idx + c.len_utf8())
+                    .unwrap_or(0);
+                self.delete_range(start, self.cursor);
+            }
+            BareKey::Char('u') if ctrl => self.delete_range(0, self.cursor),
+            BareKey::Char('k') if ctrl => self.delete_range(self.cursor, self.text.len()),
+
+            BareKey::Char(c) if key.has_no_modifiers() => self.insert(&c.to_string()),
+
+            _ => return false,
+        }
+        true
+    }
+
+    /// Inserts `text` at the cursor and moves the cursor past it.
+    pub fn insert(&mut self, text: &str) {
+        self.text.insert_str(self.cursor, text);
+        self.cursor += text.len();
+    }
+
+    /// Appends `text` at the end, leaving the cursor where it is.
+    pub fn push_str(&mut self, text: &str) {
+        self.text.push_str(text);
+    }
+
+    /// Cuts off and returns everything after the cursor.
+    pub fn split_off(&mut self) -> String {
+        self.text.split_off(self.cursor)
+    }
+
+    pub fn cursor_at_start(&self) -> bool {
+        self.cursor == 0
+    }
+
+    pub fn cursor_at_end(&self) -> bool {
+        self.cursor == self.text.len()
+    }
+
+    /// Terminal columns between the start of the text and the cursor.
+    pub fn cursor_column(&self) -> usize {
+        text::width(&self.text[..self.cursor])
+    }
+
+    /// Moves the cursor as close to `column` as possible without passing it.
+    pub fn set_cursor_column(&mut self, column: usize) {
+        let mut used = 0;
+        for (idx, grapheme) in self.text.grapheme_indices(true) {
+            used += text::grapheme_width(grapheme);
+            if used > column {
+                self.cursor = idx;
+                return;
+            }
+        }
+        self.cursor = self.text.len();
+    }
+
+    /// The text with the character under the cursor (or a trailing space) in reverse video,
+    /// scrolled horizontally so that it fits in `max_width` columns with the cursor in view.
+    pub fn render(&self, max_width: usize) -> String {
+        let end = self.next_boundary(self.cursor);
+        let under_cursor = if end > self.cursor { &self.text[self.cursor..end] } else { " " };
+        let room = max_width.saturating_sub(text::width(under_cursor).max(1));
+
+        // Show as much as fits before the cursor, then fill the rest with what follows it
+        let before = text::truncate_start(&self.text[..self.cursor], room);
+        let after = text::truncate(&self.text[end..], room - text::width(&before));
+        format!("{}\x1b[7m{}\x1b[27m{}", before, under_cursor, after)
+    }
+
+    fn delete_range(&mut self, start: usize, end: usize) {
+        self.text.replace_range(start..end, "");
+        self.cursor = start;
+    }
+
+    fn prev_boundary(&self, position: usize) -> usize {
+        self.text[..position].grapheme_indices(true).next_back().map(|(idx, _)| idx).unwrap_or(0)
+    }
+
+    fn next_boundary(&self, position: usize) -> usize {
+        self.text[position..].graphemes(true).next().map(|grapheme| position + grapheme.len()).unwrap_or(position)
+    }
+
+    fn word_start_before(&self, position: usize) -> usize {
+        // Skip separators, then the word itself
+        let mut position = position;
+        while position > 0 && !is_word_char(self.char_before(position)) {
+            position = self.prev_boundary(position);
+        }
+        while position > 0 && is_word_char(self.char_before(position)) {
+            position = self.prev_boundary(position);
+        }
+        position
+    }
+
+    fn word_end_after(&self, position: usize) -> usize {
+        let mut position = position;
+        while position < self.text.len() && !is_word_char(self.char_at(position)) {
+            position = self.next_boundary(position);
+        }
+        while position < self.text.len() && is_word_char(self.char_at(position)) {
+            position = self.next_boundary(position);
+        }
+        position
+    }
+
+    fn char_before(&self, position: usize) -> char {
+        self.text[..position].chars().next_back().unwrap_or(' ')
+    }
+
+    fn char_at(&self, position: usize) -> char {
+        self.text[position..].chars().next().unwrap_or(' ')
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(editor: &mut LineEditor, key: KeyWithModifier) {
+        assert!(editor.handle_key(&key));
+    }
+
+    fn key(bare_key: BareKey) -> KeyWithModifier {
+        KeyWithModifier::new(bare_key)
+    }
+
+    fn ctrl(bare_key: BareKey) -> KeyWithModifier {
+        KeyWithModifier::new(bare_key).with_ctrl_modifier()
+    }
+
+    fn ctrl_w(text: &str) -> String {
+        let mut editor = LineEditor::new(text.to_string());
+        press(&mut editor, ctrl(BareKey::Char('w')));
+        editor.text().to_string()
+    }
+
+    #[test]
+    fn ctrl_w_deletes_the_previous_word() {
+        assert_eq!(ctrl_w("buy some milk"), "buy some ");
+        assert_eq!(ctrl_w("buy some milk  "), "buy some ");
+        assert_eq!(ctrl_w("milk"), "");
+        assert_eq!(ctrl_w(""), "");
+    }
+
+    #[test]
+    fn ctrl_w_stops_after_multi_byte_whitespace() {
+        assert_eq!(ctrl_w("foo\u{3000}bar"), "foo\u{3000}");
+        assert_eq!(ctrl_w("foo\u{a0}bar"), "foo\u{a0}");
+        assert_eq!(ctrl_w("foo\u{3000}"), "");
+        assert_eq!(ctrl_w("café crème"), "café ");
+    }
+
+    #[test]
+    fn backspace_and_delete_remove_whole_graphemes() {
+        // e + combining acute accent, and a family emoji joined from several code points
+        let mut editor = LineEditor::new("ae\u{301}👨‍👩‍👧".to_string());
+        press(&mut editor, key(BareKey::Backspace));
+        assert_eq!(editor.text(), "ae\u{301}");
+        press(&mut editor, key(BareKey::Backspace));
+        assert_eq!(editor.text(), "a");
+
+        let mut editor = LineEditor::new("e\u{301}x".to_string());
+        press(&mut editor, key(BareKey::Home));
+        press(&mut editor, key(BareKey::Delete));
+        assert_eq!(editor.text(), "x");
+        // Nothing to delete at either end
+        press(&mut editor, key(BareKey::Backspace));
+        press(&mut editor, key(BareKey::End));
+        press(&mut editor, key(BareKey::Delete));
+        assert_eq!(editor.text(), "x");
+    }
+
+    #[test]
+    fn moves_by_words_and_kills_to_the_ends() {
+        let mut editor = LineEditor::new("one two_three, four".to_string());
+        press(&mut editor, ctrl(BareKey::Left));
+        press(&mut editor, ctrl(BareKey::Left));
+        assert_eq!(editor.cursor_column(), 4);
+        press(&mut editor, key(BareKey::Char('f')).with_alt_modifier());
+        assert_eq!(editor.cursor_column(), 13);
+        press(&mut editor, ctrl(BareKey::Char('k')));
+        assert_eq!(editor.text(), "one two_three");
+        press(&mut editor, key(BareKey::Left));
+        press(&mut editor, ctrl(BareKey::Char('u')));
+        assert_eq!(editor.text(), "e");
+        assert!(editor.cursor_at_start());
+    }
+
+    #[test]
+    fn renders_the_cursor_within_the_width() {
+        let editor = LineEditor::new("abcdef".to_string());
+        assert_eq!(editor.render(10), "abcdef\x1b[7m \x1b[27m");
+        assert_eq!(editor.render(4), "…ef\x1b[7m \x1b[27m");
+    }
+}
@@ -1,20 +1,213 @@
+// Test builds leave out the plugin entry points, so most of the plugin goes unused there
+#![cfg_attr(test, allow(dead_code))]
+
 use zellij_tile::prelude::*;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::{DefaultHasher, Hash, Hasher};
 use std::io::{self, Write};
+use std::path::PathBuf;
 use serde::{Serialize, Deserialize};
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+mod command;
+mod date;
+mod keymap;
+mod line_editor;
+mod merge;
+mod message;
+mod notes_editor;
+mod pipe;
+mod reference;
+mod project;
+mod scan;
+mod schema;
+mod storage;
+mod text;
+
+use date::Date;
+use keymap::{Action, Context, Keymap};
+use line_editor::LineEditor;
+use message::Message;
+use notes_editor::NotesEditor;
+use project::Discovery;
+use schema::{ListMeta, TodoFile};
+
+// How often to re-read the host's local date, so relative due dates roll over at midnight
+const DATE_REFRESH_INTERVAL_SECS: f64 = 60.0;
+
+// Maximum number of undo steps kept in memory
+const UNDO_LIMIT: usize = 100;
+
+// Undo and redo steps saved next to the todo file; each one is a copy of the whole list, and the
+// file is rewritten on every save
+const SAVED_UNDO_LIMIT: usize = 10;
+
+// Backups are rotated on the first save of a session and then every this many timer ticks
+const BACKUP_INTERVAL_TICKS: usize = 30;
+
+// Panes shorter than this leave out the status bar to keep room for items
+const STATUS_BAR_MIN_ROWS: usize = 6;
+
+// Rows taken by the banner shown when the todo file can't be loaded
+const LOAD_ERROR_BANNER_ROWS: usize = 4;
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 struct TodoItem {
     text: String,
     done: bool,
     id: usize,
     display_order: usize,
+    #[serde(default)]
+    list: String,
+    #[serde(default)]
+    parent: Option<usize>,
+    #[serde(default)]
+    collapsed: bool,
+    #[serde(default)]
+    priority: Priority,
+    #[serde(default)]
+    due: Option<Date>,
+    #[serde(default)]
+    notes: String,
+    #[serde(default)]
+    tags: Vec<String>,
+    // Fields added by newer builds, written back untouched
+    #[serde(flatten)]
+    extra: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Priority {
+    #[default]
+    None,
+    Low,
+    Medium,
+    High,
+    Urgent,
+}
+
+impl Priority {
+    fn raised(self) -> Self {
+        match self {
+            Priority::None => Priority::Low,
+            Priority::Low => Priority::Medium,
+            Priority::Medium => Priority::High,
+            Priority::High | Priority::Urgent => Priority::Urgent,
+        }
+    }
+
+    fn lowered(self) -> Self {
+        match self {
+            Priority::None | Priority::Low => Priority::None,
+            Priority::Medium => Priority::Low,
+            Priority::High => Priority::Medium,
+            Priority::Urgent => Priority::High,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Priority::None => "none",
+            Priority::Low => "low",
+            Priority::Medium => "medium",
+            Priority::High => "high",
+            Priority::Urgent => "urgent",
+        }
+    }
+
+    fn color(self) -> &'static str {
+        match self {
+            Priority::None => "",
+            Priority::Low => "\x1b[34m",      // Blue
+            Priority::Medium => "\x1b[33m",   // Yellow
+            Priority::High => "\x1b[31m",     // Red
+            Priority::Urgent => "\x1b[1;91m", // Bold bright red
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Snapshot {
+    items: Vec<TodoItem>,
+    #[serde(default)]
+    lists: Vec<ListMeta>,
+    next_id: usize,
+    next_display_order: usize,
+    selected_id: Option<usize>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct History {
+    undo: Vec<Snapshot>,
+    redo: Vec<Snapshot>,
+    // Fingerprint of the items the history was saved with, so a stale history is dropped
+    fingerprint: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Notice {
+    // Dismissed by the next key press
+    Info(String),
+    // Stays until the problem is resolved
+    Error(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct LoadError {
+    path: String,
+    message: String,
+    // Backup whose items are shown read-only instead, if one could be read
+    backup_path: Option<String>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
+struct ParsedItem {
+    text: String,
+    done: bool,
+    depth: usize,
+    priority: Priority,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
 enum Mode {
+    #[default]
     Normal,
     Edit,
+    Search,
+    // Choosing a list to switch to, or to move the selected item to
+    Lists,
+    // Typing the name of a new or renamed list
+    ListName,
+    // Reading the notes of the selected item
+    Details,
+    // Editing them
+    Notes,
+    // Choosing which of the selected item's file references to open
+    References,
+    // Browsing the TODO comments found in the code, read-only
+    CodeComments,
+}
+
+// Where the items go on screen, shared by rendering and mouse handling
+struct ListLayout {
+    // Indices into `items` of the items shown, and the nesting depth of every item
+    visible: Vec<usize>,
+    depths: Vec<usize>,
+    // First screen row (1-based) for items and how many rows they get
+    start_row: usize,
+    rows: usize,
+    // First visible item shown, and the rows each visible item takes
+    start_idx: usize,
+    heights: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum SearchCase {
+    // Case-insensitive unless the query contains an uppercase letter
+    #[default]
+    Smart,
+    Insensitive,
+    Sensitive,
 }
 
 #[derive(Default)]
@@ -24,25 +217,103 @@ struct State {
     next_id: usize,
     next_display_order: usize,
     mode: Mode,
-    edit_buffer: String,
+    keymap: Keymap,
+    keymap_problems: Vec<String>,
+    show_help: bool,
+    help_scroll: usize,
+    edit_buffer: LineEditor,
+    // First visible item shown in the list, kept between renders and moved by the mouse wheel
+    scroll_top: usize,
+    // Item being dragged with the mouse
+    drag_item_id: Option<usize>,
+    // Lines after the first of text pasted in Edit mode, added to the item's notes on save
+    pasted_notes: String,
+    notes_editor: NotesEditor,
+    details_scroll: usize,
     grabbed_item_id: Option<usize>,
     rows: usize,
     cols: usize,
     cwd: String,
     filename: String,
+    follow_cwd: bool,
+    global_cwd: String,
+    // Absolute host path of the folder the plugin was launched in
+    host_folder: Option<String>,
+    // Folder of the per-project todo file in use, None for the global file
+    project_dir: Option<String>,
+    // Git root of the focused pane when it has no todo file yet
+    git_root: Option<String>,
+    // Project folder (or None for the global file) we asked Zellij to switch to
+    pending_folder: Option<Option<String>>,
+    create_project_file: bool,
+    sort_by_priority: bool,
+    wrap: bool,
+    status_bar: bool,
+    // Mark an item done when its command exits successfully
+    done_on_success: bool,
+    lists: Vec<ListMeta>,
+    active_list: String,
+    list_picker_index: usize,
+    reference_index: usize,
+    // Comments found by the last scan of the code, and whether a scan is running
+    code_comments: Vec<scan::CodeComment>,
+    code_comment_index: usize,
+    scanning: bool,
+    moving_item_id: Option<usize>,
+    renaming_list: Option<String>,
+    file_extra: serde_json::Map<String, serde_json::Value>,
+    disk_base: TodoFile,
+    disk_hash: u64,
+    today: Option<Date>,
+    tag_filter: Option<String>,
+    search_query: String,
+    search_case: SearchCase,
+    search_origin: usize,
+    history: History,
+    pending_edit: Option<Snapshot>,
+    grab_recorded: bool,
+    notice: Option<Notice>,
+    load_error: Option<LoadError>,
+    backup_count: usize,
+    backup_due: bool,
+    timer_ticks: usize,
 }
 
-impl Default for Mode {
-    fn default() -> Self {
-        Mode::Normal
-    }
-}
-
+// The exported plugin entry points clash with libc's `pipe` in native test builds
+#[cfg(not(test))]
 register_plugin!(State);
 
 impl ZellijPlugin for State {
     fn load(&mut self, configuration: BTreeMap<String, String>) {
-        subscribe(&[EventType::Key, EventType::CustomMessage]);
+        self.follow_cwd = configuration.get("follow_cwd").is_some_and(|value| value == "true");
+        // Reading the application state also brings CommandPaneExited, which done_on_success needs
+        let mut permissions = vec![
+            PermissionType::ReadApplicationState,
+            PermissionType::ChangeApplicationState,
+            PermissionType::OpenFiles,
+            PermissionType::RunCommands,
+            PermissionType::ReadCliPipes,
+        ];
+        // Following the focused pane into other folders needs access beyond the launch folder
+        if self.follow_cwd {
+            permissions.push(PermissionType::FullHdAccess);
+        }
+        request_permission(&permissions);
+        subscribe(&[
+            EventType::Key,
+            EventType::Mouse,
+            EventType::CustomMessage,
+            EventType::PermissionRequestResult,
+            EventType::RunCommandResult,
+            EventType::CommandPaneExited,
+            EventType::Timer,
+            EventType::FileSystemCreate,
+            EventType::FileSystemUpdate,
+            EventType::Visible,
+            EventType::HostFolderChanged,
+            EventType::FailedToChangeHostFolder,
+        ]);
+        watch_filesystem();
 
         // Set terminal title that Zellij will use as pane name
         print!("\x1b]0;TODO\x07");
@@ -51,9 +322,40 @@ impl ZellijPlugin for State {
         // Configure file location - default to /host for current directory behavior
         self.cwd = configuration.get("cwd").cloned().unwrap_or_else(|| "/host".to_string());
         self.filename = configuration.get("filename").cloned().unwrap_or_else(|| ".zellij_todos.json".to_string());
+        self.global_cwd = self.cwd.clone();
+        self.sort_by_priority = configuration.get("sort").map(|sort| sort == "priority").unwrap_or(false);
+        self.wrap = configuration.get("wrap").is_some_and(|value| value == "true");
+        self.done_on_success = configuration.get("done_on_success").is_some_and(|value| value == "true");
+        self.status_bar = configuration.get("status_bar").is_none_or(|value| value != "false");
+        self.active_list = configuration.get("list").cloned().unwrap_or_default();
+        self.backup_count = configuration.get("backups").and_then(|count| count.parse().ok()).unwrap_or(3);
+        self.backup_due = true;
+        self.search_case = match configuration.get("search_case").map(String::as_str) {
+            Some("insensitive") => SearchCase::Insensitive,
+            Some("sensitive") => SearchCase::Sensitive,
+            _ => SearchCase::Smart,
+        };
         
+        // Remapped keys, reported once the file is loaded unless that has more pressing news
+        self.keymap_problems = self.keymap.configure(&configuration);
+
         // Load persisted todos from file system if available
         self.load_todos();
+        if !self.keymap_problems.is_empty() && self.notice.is_none() {
+            self.notice = Some(Notice::Info(format!("Key bindings: {} (see ?)", self.keymap_problems.join("; "))));
+        }
+
+        // Start in the configured list, creating it if it doesn't exist yet
+        if !self.active_list.is_empty() && !self.lists.iter().any(|list| list.name == self.active_list) {
+            self.lists.push(ListMeta {
+                name: self.active_list.clone(),
+                ..Default::default()
+            });
+        }
+        self.normalize_lists();
+
+        // Periodically refresh today's date for due date labels
+        set_timeout(DATE_REFRESH_INTERVAL_SECS);
     }
 
     fn update(&mut self, event: Event) -> bool {
@@ -63,22 +365,97 @@ impl ZellijPlugin for State {
             Event::Key(key) => {
                 should_render = self.handle_key(key);
             }
-            Event::CustomMessage(message, _) => {
-                // Handle plugin toggle - if we receive a message while visible, close
-                if message.contains("toggle") {
-                    hide_self();
+            Event::Mouse(mouse) => {
+                should_render = self.handle_mouse(mouse);
+            }
+            Event::CustomMessage(message, payload) => {
+                // Reply to dump_session_layout
+                if message == "session_layout" {
+                    self.find_project_file(&payload);
                     return false;
-                } else if !message.is_empty() {
-                    // Handle pasted text
-                    should_render = self.handle_paste(message);
+                }
+
+                should_render = self.handle_message(&message, &payload);
+            }
+            Event::PermissionRequestResult(PermissionStatus::Granted) => {
+                self.request_today();
+                self.request_host_folder();
+                if self.follow_cwd {
+                    dump_session_layout();
+                }
+            }
+            Event::Visible(true) if self.follow_cwd => {
+                // Look for the focused pane's project whenever the plugin is brought up
+                dump_session_layout();
+            }
+            Event::HostFolderChanged(_) => {
+                should_render = self.open_pending_folder();
+            }
+            Event::FailedToChangeHostFolder(error) => {
+                self.pending_folder = None;
+                self.create_project_file = false;
+                self.notice = Some(Notice::Info(format!(
+                    "Could not switch todo file: {}",
+                    error.unwrap_or_else(|| "unknown error".to_string())
+                )));
+                should_render = true;
+            }
+            Event::Timer(_) => {
+                self.request_today();
+                set_timeout(DATE_REFRESH_INTERVAL_SECS);
+
+                self.timer_ticks += 1;
+                if self.timer_ticks.is_multiple_of(BACKUP_INTERVAL_TICKS) {
+                    self.backup_due = true;
+                }
+
+                // Filesystem events only cover the host folder, so also poll for a file elsewhere
+                if !matches!(self.mode, Mode::Edit | Mode::Notes) {
+                    should_render = self.merge_external_changes();
+                }
+            }
+            Event::FileSystemCreate(paths) | Event::FileSystemUpdate(paths) => {
+                let todo_file_changed = paths
+                    .iter()
+                    .any(|(path, _)| path.file_name().is_some_and(|name| name == self.filename.as_str()));
+                if todo_file_changed && !matches!(self.mode, Mode::Edit | Mode::Notes) {
+                    should_render = self.merge_external_changes();
                 }
             }
+            Event::CommandPaneExited(_, exit_code, context) => {
+                should_render = self.handle_command_pane_exit(exit_code, context);
+            }
+            Event::RunCommandResult(exit_code, stdout, stderr, context) => {
+                should_render = self.handle_command_result(exit_code, stdout, stderr, context);
+            }
             _ => {}
         }
 
         should_render
     }
 
+    fn pipe(&mut self, pipe_message: PipeMessage) -> bool {
+        // A missing payload only marks the end of a pipe's input
+        let Some(payload) = pipe_message.payload else {
+            return false;
+        };
+
+        // Typed messages work from any source; plain commands are for the command line
+        let is_cli = matches!(pipe_message.source, PipeSource::Cli(_));
+        if !is_cli || pipe_message.name.starts_with("zellij-todo:") {
+            return self.handle_message(&pipe_message.name, &payload);
+        }
+        let reply = pipe::parse(&pipe_message.name, &payload).and_then(|command| self.run_pipe_command(command));
+        if let PipeSource::Cli(pipe_id) = &pipe_message.source {
+            let output = match &reply {
+                Ok(output) => output.clone(),
+                Err(error) => format!("error: {}", error),
+            };
+            cli_pipe_output(pipe_id, &format!("{}\n", output));
+        }
+        reply.is_ok()
+    }
+
     fn render(&mut self, rows: usize, cols: usize) {
         self.rows = rows;
         self.cols = cols;
@@ -86,241 +463,1568 @@ impl ZellijPlugin for State {
         // Clear screen
         print!("\x1b[2J\x1b[H");
 
-        if self.items.is_empty() && self.mode == Mode::Normal {
+        if self.show_help {
+            self.render_help();
+        } else if matches!(self.mode, Mode::Lists | Mode::ListName) {
+            self.render_list_picker();
+        } else if self.mode == Mode::References {
+            self.render_reference_picker();
+        } else if self.mode == Mode::CodeComments {
+            self.render_code_comments();
+        } else if matches!(self.mode, Mode::Details | Mode::Notes) && self.selected_item_index().is_some() {
+            self.render_item_details();
+        } else if self.items.is_empty()
+            && self.lists.len() <= 1
+            && !self.follow_cwd
+            && self.mode == Mode::Normal
+            && self.load_error.is_none()
+        {
             self.render_empty_state();
         } else {
-            self.render_todo_list();
+            let layout = self.list_layout();
+            self.scroll_top = layout.start_idx;
+            self.render_todo_list(&layout);
         }
     }
 }
 
 impl State {
-    fn handle_key(&mut self, key: KeyWithModifier) -> bool {
-        match self.mode {
-            Mode::Normal => self.handle_normal_mode_key(key),
-            Mode::Edit => self.handle_edit_mode_key(key),
-        }
+    fn request_today(&self) {
+        // Wasm has no reliable local clock or time zone, so ask the host for its local date
+        let mut context = BTreeMap::new();
+        context.insert("source".to_string(), "today".to_string());
+        run_command(&["date", "+%Y-%m-%d"], context);
     }
 
-    fn handle_normal_mode_key(&mut self, key: KeyWithModifier) -> bool {
-        if self.items.is_empty() {
-            // Special handling when no items exist
-            match key.bare_key {
-                BareKey::Char('a') if key.has_no_modifiers() => {
-                    self.add_new_item();
-                    return true;
-                }
-                BareKey::Char('q') if key.has_no_modifiers() => {
-                    hide_self();
-                    return false;
-                }
-                _ => return false,
+    fn handle_command_result(&mut self, exit_code: Option<i32>, stdout: Vec<u8>, stderr: Vec<u8>, context: BTreeMap<String, String>) -> bool {
+        if exit_code != Some(0) {
+            // Only a failed scan has someone waiting for it
+            if context.get("source").map(String::as_str) != Some("scan") {
+                return false;
             }
+            let stderr = String::from_utf8_lossy(&stderr);
+            let reason = match (stderr.lines().find(|line| !line.trim().is_empty()), exit_code) {
+                (Some(line), _) => line.trim().to_string(),
+                (None, Some(code)) => format!("exit code {}", code),
+                (None, None) => "no exit code".to_string(),
+            };
+            self.scanning = false;
+            self.notice = Some(Notice::Error(format!("Could not scan for code TODOs: {}", reason)));
+            return true;
         }
 
-        match key.bare_key {
-            // Navigation
-            BareKey::Up if key.has_no_modifiers() => {
-                if self.grabbed_item_id.is_some() {
-                    self.move_grabbed_item_up();
-                } else if self.selected_index > 0 {
-                    self.selected_index -= 1;
-                }
-                true
-            }
-            BareKey::Down if key.has_no_modifiers() => {
-                if self.grabbed_item_id.is_some() {
-                    self.move_grabbed_item_down();
-                } else if self.selected_index < self.items.len().saturating_sub(1) {
-                    self.selected_index += 1;
-                }
-                true
+        match context.get("source").map(String::as_str) {
+            Some("today") => {
+                let today = Date::parse_iso(String::from_utf8_lossy(&stdout).trim());
+                let changed = today.is_some() && today != self.today;
+                self.today = today.or(self.today);
+                changed
             }
-            
-            // Vim-style navigation
-            BareKey::Char('k') if key.has_no_modifiers() => {
-                if self.grabbed_item_id.is_some() {
-                    self.move_grabbed_item_up();
-                } else if self.selected_index > 0 {
-                    self.selected_index -= 1;
-                }
-                true
+            Some("host_folder") => {
+                self.host_folder = Some(String::from_utf8_lossy(&stdout).trim().to_string());
+                false
             }
-            BareKey::Char('j') if key.has_no_modifiers() => {
-                if self.grabbed_item_id.is_some() {
-                    self.move_grabbed_item_down();
-                } else if self.selected_index < self.items.len().saturating_sub(1) {
-                    self.selected_index += 1;
-                }
+            Some("project") => self.apply_discovery(project::parse_discovery(&String::from_utf8_lossy(&stdout))),
+            Some("scan") if context.get("todos_path") == Some(&self.todos_path()) => {
+                self.code_comments = scan::parse_output(&String::from_utf8_lossy(&stdout));
+                self.code_comment_index = self.code_comment_index.min(self.code_comments.len().saturating_sub(1));
+                self.scanning = false;
                 true
             }
-
-            // Toggle completion (or exit grab mode)
-            BareKey::Char(' ') if key.has_no_modifiers() => {
-                if self.grabbed_item_id.is_some() {
-                    self.grabbed_item_id = None;
+            Some("scan") => {
+                // Started for a todo file we have since left; scan the current one if still in view
+                if self.mode == Mode::CodeComments {
+                    self.start_code_scan();
                 } else {
-                    self.toggle_current_item();
+                    self.scanning = false;
                 }
-                true
+                false
             }
+            _ => false,
+        }
+    }
 
-            // Grab/release item for reordering
-            BareKey::Char('g') if key.has_no_modifiers() => {
-                self.toggle_grab();
-                true
-            }
+    fn request_host_folder(&self) {
+        // Remember where the plugin started, to return to the global file later and to run
+        // commands next to it
+        let mut context = BTreeMap::new();
+        context.insert("source".to_string(), "host_folder".to_string());
+        run_command(&["pwd"], context);
+    }
 
-            // Add new item (or exit grab mode)
-            BareKey::Char('a') if key.has_no_modifiers() => {
-                if self.grabbed_item_id.is_some() {
-                    self.grabbed_item_id = None;
-                } else {
-                    self.add_new_item();
-                }
-                true
-            }
+    fn run_current_item_command(&mut self, floating: bool) {
+        let Some(idx) = self.selected_item_index() else {
+            return;
+        };
+        let Some(command) = command::item_command(&self.items[idx].text) else {
+            self.notice = Some(Notice::Info("No command in this item - write it after \"$ \" or in `backticks`".to_string()));
+            return;
+        };
 
-            // Edit current item (or release grab if in grab mode)
-            BareKey::Enter if key.has_no_modifiers() => {
-                if self.grabbed_item_id.is_some() {
-                    self.grabbed_item_id = None;
-                } else {
-                    self.start_editing_current();
-                }
-                true
-            }
+        // The exit is matched back to the item, in the file it came from
+        let mut context = BTreeMap::new();
+        context.insert("source".to_string(), "item_command".to_string());
+        context.insert("item_id".to_string(), self.items[idx].id.to_string());
+        context.insert("todos_path".to_string(), self.todos_path());
+        let command_to_run = CommandToRun {
+            path: PathBuf::from("sh"),
+            args: vec!["-c".to_string(), command],
+            cwd: self.command_dir(),
+        };
+        if floating {
+            open_command_pane_floating(command_to_run, None, context);
+        } else {
+            open_command_pane(command_to_run, context);
+        }
+    }
 
-            // Delete current item (or exit grab mode)
-            BareKey::Delete if key.has_no_modifiers() => {
-                if self.grabbed_item_id.is_some() {
-                    self.grabbed_item_id = None;
-                } else {
-                    self.delete_current_item();
-                }
-                true
-            }
-            BareKey::Backspace if key.has_no_modifiers() => {
-                if self.grabbed_item_id.is_some() {
-                    self.grabbed_item_id = None;
-                } else {
-                    self.delete_current_item();
+    fn current_item_references(&self) -> Vec<reference::Reference> {
+        // Each file and location once, in the order they appear in the text
+        let mut references: Vec<reference::Reference> = Vec::new();
+        if let Some(idx) = self.selected_item_index() {
+            for (_, _, reference) in reference::find(&self.items[idx].text) {
+                if !references.contains(&reference) {
+                    references.push(reference);
                 }
-                true
             }
+        }
+        references
+    }
 
-            // Paste with Ctrl+V
-            BareKey::Char('v') if key.has_modifiers(&[KeyModifier::Ctrl]) => {
-                // Ctrl+V in normal mode - paste will be handled via CustomMessage event
-                // Just return true to trigger re-render
-                true
+    fn open_current_item_reference(&mut self) {
+        // Open the only reference right away, or let the user choose between several
+        let references = self.current_item_references();
+        match references.as_slice() {
+            [] => self.notice = Some(Notice::Info("No file reference in this item - write it like src/main.rs:412".to_string())),
+            [reference] => self.open_reference(reference),
+            _ => {
+                self.reference_index = 0;
+                self.mode = Mode::References;
             }
+        }
+    }
 
-            // Quit
-            BareKey::Char('q') if key.has_no_modifiers() => {
-                hide_self();
-                false
+    fn open_reference(&self, reference: &reference::Reference) {
+        // Zellij opens the file in $EDITOR; it takes a line but no column
+        let file_to_open = FileToOpen {
+            path: PathBuf::from(&reference.path),
+            line_number: reference.line,
+            cwd: self.command_dir(),
+        };
+        open_file_floating(file_to_open, None, BTreeMap::new());
+    }
+
+    fn start_code_scan(&mut self) {
+        // Scan the folder of the todo file on the host; results for another file are dropped
+        let mut context = BTreeMap::new();
+        context.insert("source".to_string(), "scan".to_string());
+        context.insert("todos_path".to_string(), self.todos_path());
+        let max_comments = scan::MAX_COMMENTS.to_string();
+        run_command_with_env_variables_and_cwd(
+            &["sh", "-c", scan::SCAN_SCRIPT, "sh", &self.filename, &max_comments],
+            BTreeMap::new(),
+            self.command_dir().unwrap_or_else(|| PathBuf::from(".")),
+            context,
+        );
+        self.scanning = true;
+    }
+
+    fn promote_code_comment(&mut self) {
+        // The new item carries the comment's location, so `f` jumps back to it
+        let Some(comment) = self.code_comments.get(self.code_comment_index).cloned() else {
+            return;
+        };
+        if self.load_error.is_some() {
+            return;
+        }
+        let location = comment.location();
+        if self.is_promoted(&comment) {
+            self.notice = Some(Notice::Info(format!("{} is already in the list", location)));
+            return;
+        }
+        let text = if comment.text.is_empty() { comment.kind } else { comment.text.as_str() };
+        self.add_item_at_end(&format!("{} {}", text, location));
+        self.notice = Some(Notice::Info(format!("Added {} to {}", location, self.active_list)));
+    }
+
+    fn is_promoted(&self, comment: &scan::CodeComment) -> bool {
+        let location = comment.location();
+        self.items
+            .iter()
+            .any(|item| item.list == self.active_list && item.text.split_whitespace().any(|word| word == location))
+    }
+
+    fn command_dir(&self) -> Option<PathBuf> {
+        // Commands run and file references are found in the folder of the todo file: the
+        // project, or for the global file the host path behind the plugin's /host folder. None
+        // leaves it to Zellij, which uses the folder the plugin was launched in.
+        if let Some(dir) = &self.project_dir {
+            return Some(PathBuf::from(dir));
+        }
+        match self.cwd.strip_prefix("/host") {
+            Some("") | Some("/") => None,
+            Some(rest) if rest.starts_with('/') => self.host_folder.as_ref().map(|folder| PathBuf::from(format!("{}{}", folder.trim_end_matches('/'), rest))),
+            _ => Some(PathBuf::from(&self.cwd)),
+        }
+    }
+
+    fn handle_command_pane_exit(&mut self, exit_code: Option<i32>, context: BTreeMap<String, String>) -> bool {
+        if !self.done_on_success || exit_code != Some(0) || self.load_error.is_some() {
+            return false;
+        }
+        if context.get("source").map(String::as_str) != Some("item_command") || context.get("todos_path") != Some(&self.todos_path()) {
+            return false;
+        }
+        let Some(id) = context.get("item_id").and_then(|id| id.parse().ok()) else {
+            return false;
+        };
+        let Some(text) = self.items.iter().find(|item| item.id == id && !item.done).map(|item| item.text.clone()) else {
+            return false;
+        };
+
+        self.mark_items_done(&[id]);
+        self.notice = Some(Notice::Info(format!("Command succeeded, marked done: {}", text)));
+        true
+    }
+
+    fn find_project_file(&self, layout: &str) {
+        if let Some(cwd) = project::focused_pane_cwd(layout) {
+            let mut context = BTreeMap::new();
+            context.insert("source".to_string(), "project".to_string());
+            run_command(&["sh", "-c", project::FIND_TODO_FILE_SCRIPT, "sh", &cwd, &self.filename], context);
+        }
+    }
+
+    fn apply_discovery(&mut self, discovery: Discovery) -> bool {
+        let target = match discovery {
+            Discovery::File(dir) => {
+                self.git_root = None;
+                Some(dir)
             }
-            BareKey::Esc if key.has_no_modifiers() => {
-                if self.grabbed_item_id.is_some() {
-                    self.grabbed_item_id = None;
-                    true
-                } else {
-                    hide_self();
-                    false
-                }
+            Discovery::GitRoot(dir) => {
+                self.git_root = Some(dir);
+                None
             }
-            // Handle Ctrl+k to close (same as opening keybind)
-            BareKey::Char('k') if key.has_modifiers(&[KeyModifier::Ctrl]) => {
-                hide_self();
-                false
+            Discovery::Nothing => {
+                self.git_root = None;
+                None
             }
+        };
 
-            _ => false,
+        // Don't pull the list away while an item is being edited or moved
+        if target == self.project_dir || self.mode != Mode::Normal || self.grabbed_item_id.is_some() {
+            return true;
         }
+        self.switch_todo_folder(target);
+        false
     }
 
-    fn handle_edit_mode_key(&mut self, key: KeyWithModifier) -> bool {
-        match key.bare_key {
-            // Save and exit edit mode
-            BareKey::Enter if key.has_no_modifiers() => {
-                self.save_edit();
+    fn switch_todo_folder(&mut self, target: Option<String>) {
+        let folder = match target.clone().or_else(|| self.host_folder.clone()) {
+            Some(folder) => folder,
+            None => return,
+        };
+        self.pending_folder = Some(target);
+        change_host_folder(PathBuf::from(folder));
+    }
+
+    fn open_pending_folder(&mut self) -> bool {
+        let target = match self.pending_folder.take() {
+            Some(target) => target,
+            None => return false,
+        };
+
+        // Project files live in the new host folder, the global one where it was configured
+        self.cwd = if target.is_some() { "/host".to_string() } else { self.global_cwd.clone() };
+        self.project_dir = target;
+        self.tag_filter = None;
+        self.search_query.clear();
+        self.backup_due = true;
+        self.retry_load();
+
+        if std::mem::take(&mut self.create_project_file) {
+            self.save_todos();
+        }
+        if self.load_error.is_none() {
+            self.notice = Some(Notice::Info(format!("Switched to {} todo list", self.todo_file_label())));
+        }
+        true
+    }
+
+    fn start_project_file(&mut self) {
+        if let (None, Some(git_root)) = (&self.project_dir, self.git_root.clone()) {
+            self.create_project_file = true;
+            self.switch_todo_folder(Some(git_root));
+        }
+    }
+
+    fn todo_file_label(&self) -> String {
+        match &self.project_dir {
+            Some(dir) => dir.rsplit('/').find(|part| !part.is_empty()).unwrap_or(dir).to_string(),
+            None => "global".to_string(),
+        }
+    }
+
+    fn handle_key(&mut self, key: KeyWithModifier) -> bool {
+        if matches!(self.notice, Some(Notice::Info(_))) {
+            self.notice = None;
+        }
+
+        if self.show_help {
+            return self.handle_help_key(key);
+        }
+        let context = self.key_context();
+        if self.keymap.action(context, &key) == Some(Action::Help) {
+            self.show_help = true;
+            self.help_scroll = 0;
+            return true;
+        }
+
+        match context {
+            Context::LoadError => self.handle_load_error_key(key),
+            Context::Normal | Context::Grab => self.handle_normal_mode_key(key),
+            Context::Edit => self.handle_edit_mode_key(key),
+            Context::Search => self.handle_search_mode_key(key),
+            Context::Lists => self.handle_list_picker_key(key),
+            Context::ListName => self.handle_list_name_key(key),
+            Context::Details => self.handle_details_key(key),
+            Context::Notes => self.handle_notes_key(key),
+            Context::References => self.handle_reference_picker_key(key),
+            Context::CodeComments => self.handle_code_comments_key(key),
+            Context::Help => self.handle_help_key(key),
+        }
+    }
+
+    fn handle_mouse(&mut self, mouse: Mouse) -> bool {
+        // The mouse only works on the list itself, not in editors, pickers or the help overlay
+        if self.mode != Mode::Normal || self.load_error.is_some() || self.show_help {
+            return false;
+        }
+        if matches!(self.notice, Some(Notice::Info(_))) {
+            self.notice = None;
+        }
+
+        match mouse {
+            Mouse::ScrollUp(lines) if self.grabbed_item_id.is_none() => {
+                self.scroll_list(-(lines.max(1) as isize));
                 true
             }
-
-            // Cancel edit mode
-            BareKey::Esc if key.has_no_modifiers() => {
-                self.cancel_edit();
+            Mouse::ScrollDown(lines) if self.grabbed_item_id.is_none() => {
+                self.scroll_list(lines.max(1) as isize);
                 true
             }
+            Mouse::LeftClick(line, column) => {
+                let layout = self.list_layout();
+                let Some((view_idx, item_line)) = self.item_at_row(&layout, line + 1) else {
+                    return false;
+                };
+                self.grabbed_item_id = None;
+                self.drag_item_id = None;
+                self.selected_index = view_idx;
 
-            // Backspace
-            BareKey::Backspace if key.has_no_modifiers() => {
-                self.edit_buffer.pop();
+                // Clicking the bullet toggles the item; clicking anywhere else starts a drag
+                let idx = layout.visible[view_idx];
+                let bullet_column = 2 + layout.depths[idx] * 2;
+                if item_line == 0 && (bullet_column..=bullet_column + 1).contains(&column) {
+                    self.toggle_current_item();
+                } else {
+                    self.drag_item_id = Some(self.items[idx].id);
+                }
                 true
             }
-
-            // Paste with Ctrl+V
-            BareKey::Char('v') if key.has_modifiers(&[KeyModifier::Ctrl]) => {
-                // Ctrl+V in edit mode - paste will be handled via CustomMessage event
-                // Just return true to trigger re-render
+            Mouse::Hold(line, _) => {
+                let Some(drag_id) = self.drag_item_id else {
+                    return false;
+                };
+                if self.grabbed_item_id != Some(drag_id) {
+                    self.select_item_by_id(drag_id);
+                    self.grabbed_item_id = Some(drag_id);
+                    self.grab_recorded = false;
+                }
+                self.drag_grabbed_item_to(line + 1);
                 true
             }
-
-            // Type characters
-            BareKey::Char(c) if key.has_no_modifiers() => {
-                self.edit_buffer.push(c);
+            Mouse::Release(..) if self.drag_item_id.is_some() => {
+                self.drag_item_id = None;
+                self.grabbed_item_id = None;
                 true
             }
-
             _ => false,
         }
     }
 
-    fn toggle_current_item(&mut self) {
-        if let Some(item) = self.items.get_mut(self.selected_index) {
-            let original_cursor_position = self.selected_index;
-            item.done = !item.done;
-            self.sort_items();
-            
-            // Keep cursor at the same visual position instead of following the moved item
-            self.selected_index = std::cmp::min(original_cursor_position, self.items.len().saturating_sub(1));
-            
-            self.save_todos();
+    fn scroll_list(&mut self, lines: isize) {
+        // Move the first item shown, never past the point where the last item is at the bottom,
+        // then keep the selection within what is on screen
+        let layout = self.list_layout();
+        let mut last_start = layout.heights.len().saturating_sub(1);
+        let mut tail_rows = layout.heights.last().copied().unwrap_or(0);
+        while last_start > 0 && tail_rows + layout.heights[last_start - 1] <= layout.rows {
+            last_start -= 1;
+            tail_rows += layout.heights[last_start];
+        }
+        let start_idx = layout.start_idx.saturating_add_signed(lines).min(last_start);
+        self.scroll_top = start_idx;
+
+        let mut last_shown = start_idx;
+        let mut used_rows = 0;
+        for (view_idx, height) in layout.heights.iter().enumerate().skip(start_idx) {
+            used_rows += height;
+            if used_rows > layout.rows {
+                break;
+            }
+            last_shown = view_idx;
         }
+        self.selected_index = self.selected_index.clamp(start_idx, last_shown);
     }
 
-    fn toggle_grab(&mut self) {
-        if let Some(grabbed_id) = self.grabbed_item_id {
-            // Release the grabbed item
-            if grabbed_id == self.get_current_item_id() {
-                self.grabbed_item_id = None;
+    fn item_at_row(&self, layout: &ListLayout, row: isize) -> Option<(usize, usize)> {
+        // The visible item drawn on a screen row, and which of its rows it is
+        let mut first_row = layout.start_row as isize;
+        let end_row = (layout.start_row + layout.rows) as isize;
+        for (view_idx, &height) in layout.heights.iter().enumerate().skip(layout.start_idx) {
+            if row < first_row || first_row >= end_row {
+                break;
             }
-        } else {
-            // Grab the current item
-            self.grabbed_item_id = Some(self.get_current_item_id());
+            if row < first_row + height as isize {
+                return Some((view_idx, (row - first_row) as usize));
+            }
+            first_row += height as isize;
         }
+        None
     }
 
-    fn get_current_item_id(&self) -> usize {
-        self.items.get(self.selected_index).map(|item| item.id).unwrap_or(0)
-    }
+    fn drag_grabbed_item_to(&mut self, row: isize) {
+        // Pick the item under the pointer, or the one just out of view when dragging past the
+        // edge of the list so that it scrolls, then move the grabbed item past its siblings
+        // until it gets there
+        let layout = self.list_layout();
+        let last_row = (layout.start_row + layout.rows).saturating_sub(1) as isize;
+        let target = if row < layout.start_row as isize {
+            layout.start_idx.saturating_sub(1)
+        } else {
+            match self.item_at_row(&layout, row.min(last_row)) {
+                Some((view_idx, _)) if row <= last_row => view_idx,
+                Some((view_idx, _)) => view_idx + 1,
+                None => layout.heights.len().saturating_sub(1),
+            }
+        };
 
-    fn move_grabbed_item_up(&mut self) {
-        if let Some(grabbed_id) = self.grabbed_item_id {
-            let grabbed_item_done = self.items.iter().find(|item| item.id == grabbed_id).map(|item| item.done).unwrap_or(false);
+        let moving_down = self.next_visible_sibling().is_some_and(|sibling| sibling <= target);
+        // Every step passes a sibling, so there are never more steps than items
+        for _ in 0..self.items.len() {
+            let before = self.selected_index;
+            if moving_down && self.next_visible_sibling().is_some_and(|sibling| sibling <= target) {
+                self.move_grabbed_item_down();
+            } else if !moving_down && self.previous_visible_sibling().is_some_and(|sibling| sibling >= target) {
+                self.move_grabbed_item_up();
+            } else {
+                break;
+            }
+            if self.selected_index == before {
+                break;
+            }
+        }
+    }
+
+    fn next_visible_sibling(&self) -> Option<usize> {
+        let visible = self.visible_indices();
+        let item = &self.items[*visible.get(self.selected_index)?];
+        (self.selected_index + 1..visible.len())
+            .find(|&view_idx| self.items[visible[view_idx]].parent == item.parent && self.items[visible[view_idx]].list == item.list)
+    }
+
+    fn previous_visible_sibling(&self) -> Option<usize> {
+        let visible = self.visible_indices();
+        let item = &self.items[*visible.get(self.selected_index)?];
+        (0..self.selected_index)
+            .rev()
+            .find(|&view_idx| self.items[visible[view_idx]].parent == item.parent && self.items[visible[view_idx]].list == item.list)
+    }
+
+    fn key_context(&self) -> Context {
+        // The context below the help overlay, which handles its own keys
+        if self.load_error.is_some() {
+            return Context::LoadError;
+        }
+        match self.mode {
+            Mode::Normal if self.grabbed_item_id.is_some() => Context::Grab,
+            Mode::Normal => Context::Normal,
+            Mode::Edit => Context::Edit,
+            Mode::Search => Context::Search,
+            Mode::Lists => Context::Lists,
+            Mode::ListName => Context::ListName,
+            Mode::Details => Context::Details,
+            Mode::Notes => Context::Notes,
+            Mode::References => Context::References,
+            Mode::CodeComments => Context::CodeComments,
+        }
+    }
+
+    fn handle_help_key(&mut self, key: KeyWithModifier) -> bool {
+        let page = self.rows.saturating_sub(3).max(1);
+        let max_scroll = self.help_lines().len().saturating_sub(self.rows.saturating_sub(2));
+        match self.keymap.action(Context::Help, &key) {
+            Some(Action::Up) => self.help_scroll = self.help_scroll.saturating_sub(1),
+            Some(Action::Down) => self.help_scroll = (self.help_scroll + 1).min(max_scroll),
+            Some(Action::PageUp) => self.help_scroll = self.help_scroll.saturating_sub(page),
+            Some(Action::PageDown) => self.help_scroll = (self.help_scroll + page).min(max_scroll),
+            Some(Action::Back) => self.show_help = false,
+            _ => return false,
+        }
+        true
+    }
+
+    fn handle_load_error_key(&mut self, key: KeyWithModifier) -> bool {
+        // The list is read-only until the broken file is dealt with, so only navigation works
+        match self.keymap.action(Context::LoadError, &key) {
+            Some(Action::Up) => {
+                self.selected_index = self.selected_index.saturating_sub(1);
+                true
+            }
+            Some(Action::Down) => {
+                if self.selected_index < self.visible_indices().len().saturating_sub(1) {
+                    self.selected_index += 1;
+                }
+                true
+            }
+            Some(Action::Retry) => {
+                self.retry_load();
+                true
+            }
+            Some(Action::UseBackup) => {
+                self.set_aside_broken_file();
+                true
+            }
+            Some(Action::Quit) => {
+                hide_self();
+                false
+            }
+            _ => false,
+        }
+    }
+
+    fn handle_normal_mode_key(&mut self, key: KeyWithModifier) -> bool {
+        // While an item is grabbed, movement moves it; keys not bound there work as usual
+        if self.grabbed_item_id.is_some() {
+            match self.keymap.action(Context::Grab, &key) {
+                Some(Action::MoveUp) => self.move_grabbed_item_up(),
+                Some(Action::MoveDown) => self.move_grabbed_item_down(),
+                Some(Action::Grab) => self.toggle_grab(),
+                Some(Action::Release) => self.grabbed_item_id = None,
+                _ => return self.handle_normal_action(key),
+            }
+            return true;
+        }
+        self.handle_normal_action(key)
+    }
+
+    fn handle_normal_action(&mut self, key: KeyWithModifier) -> bool {
+        let action = match self.keymap.action(Context::Normal, &key) {
+            Some(action) => action,
+            None => return false,
+        };
+
+        // Without any items, only actions that don't work on the selected one do anything
+        if self.items.is_empty() && action.needs_item() {
+            return false;
+        }
+
+        match action {
+            // Navigation
+            Action::Up => {
+                if self.selected_index > 0 {
+                    self.selected_index -= 1;
+                }
+                true
+            }
+            Action::Down => {
+                if self.selected_index < self.visible_indices().len().saturating_sub(1) {
+                    self.selected_index += 1;
+                }
+                true
+            }
+
+            // Collapse current item (or jump to its parent)
+            Action::Collapse => {
+                self.collapse_current_item();
+                true
+            }
+
+            // Expand current item
+            Action::Expand => {
+                self.expand_current_item();
+                true
+            }
+
+            // Indent under the sibling above / outdent to the parent's level
+            Action::Indent => {
+                self.indent_current_item();
+                true
+            }
+            Action::Outdent => {
+                self.outdent_current_item();
+                true
+            }
+
+            // Raise/lower priority
+            Action::RaisePriority => {
+                self.change_current_priority(Priority::raised);
+                true
+            }
+            Action::LowerPriority => {
+                self.change_current_priority(Priority::lowered);
+                true
+            }
+
+            // Cycle the tag filter through known tags / clear it
+            Action::NextTag => {
+                self.cycle_tag_filter();
+                true
+            }
+            Action::ClearTag => {
+                self.set_tag_filter(None);
+                true
+            }
+
+            // Toggle grouping by priority for the active list
+            Action::SortByPriority => {
+                let sort_by_priority = !self.list_sorts_by_priority(&self.active_list);
+                let position = self.active_list_position();
+                self.lists[position].sort_by_priority = Some(sort_by_priority);
+                self.sort_items();
+                self.save_todos();
+                true
+            }
+
+            // Wrap long items over several rows instead of truncating them
+            Action::Wrap => {
+                self.wrap = !self.wrap;
+                true
+            }
+
+            // Show the item's notes
+            Action::Details => {
+                self.details_scroll = 0;
+                self.mode = Mode::Details;
+                true
+            }
+            Action::RunCommand => {
+                self.run_current_item_command(true);
+                true
+            }
+            Action::RunCommandTiled => {
+                self.run_current_item_command(false);
+                true
+            }
+            Action::OpenReference => {
+                self.open_current_item_reference();
+                true
+            }
+            Action::ScanCode => {
+                self.start_code_scan();
+                self.mode = Mode::CodeComments;
+                true
+            }
+
+            // Switch between lists, or move the selected item to another one
+            Action::NextList => {
+                self.cycle_list(true);
+                true
+            }
+            Action::PrevList => {
+                self.cycle_list(false);
+                true
+            }
+            Action::Lists => {
+                self.open_list_picker(false);
+                true
+            }
+            Action::MoveToList => {
+                self.open_list_picker(true);
+                true
+            }
+
+            // Start a todo file at the focused pane's git root
+            Action::StartProjectFile if self.follow_cwd => {
+                self.start_project_file();
+                true
+            }
+
+            // Toggle completion
+            Action::ToggleDone => {
+                self.toggle_current_item();
+                true
+            }
+
+            // Grab item for reordering
+            Action::Grab => {
+                self.toggle_grab();
+                true
+            }
+
+            // Add new item
+            Action::Add => {
+                self.add_new_item();
+                true
+            }
+
+            // Edit current item
+            Action::Edit => {
+                self.start_editing_current();
+                true
+            }
+
+            // Delete current item
+            Action::Delete => {
+                self.delete_current_item();
+                true
+            }
+
+            // Undo/redo
+            Action::Undo => {
+                self.undo();
+                true
+            }
+            Action::Redo => {
+                self.redo();
+                true
+            }
+
+            // Search
+            Action::Search => {
+                self.start_search();
+                true
+            }
+            Action::NextMatch => {
+                self.jump_to_match(true);
+                true
+            }
+            Action::PrevMatch => {
+                self.jump_to_match(false);
+                true
+            }
+
+            // Paste with Ctrl+V
+            Action::Paste => {
+                // Ctrl+V in normal mode - paste will be handled via CustomMessage event
+                // Just return true to trigger re-render
+                true
+            }
+
+            // Quit
+            Action::Quit => {
+                hide_self();
+                false
+            }
+            Action::Back => {
+                if !self.search_query.is_empty() {
+                    // Clear search highlighting before closing
+                    self.search_query.clear();
+                    true
+                } else {
+                    hide_self();
+                    false
+                }
+            }
+
+            _ => false,
+        }
+    }
+
+    fn handle_edit_mode_key(&mut self, key: KeyWithModifier) -> bool {
+        match self.keymap.action(Context::Edit, &key) {
+            // Save and exit edit mode
+            Some(Action::Save) => {
+                self.save_edit();
+                true
+            }
+
+            // Cancel edit mode
+            Some(Action::Cancel) => {
+                self.cancel_edit();
+                true
+            }
+
+            // Paste with Ctrl+V
+            Some(Action::Paste) => {
+                // Ctrl+V in edit mode - paste will be handled via CustomMessage event
+                // Just return true to trigger re-render
+                true
+            }
+
+            // Typing, cursor movement and deletion
+            _ => self.edit_buffer.handle_key(&key),
+        }
+    }
+
+    fn handle_search_mode_key(&mut self, key: KeyWithModifier) -> bool {
+        match self.keymap.action(Context::Search, &key) {
+            // Keep the query and its highlighting, return to Normal
+            Some(Action::Save) => {
+                self.mode = Mode::Normal;
+                true
+            }
+
+            // Drop the query and go back to where the search started
+            Some(Action::Cancel) => {
+                self.search_query.clear();
+                self.selected_index = self.search_origin;
+                self.clamp_selection();
+                self.mode = Mode::Normal;
+                true
+            }
+
+            // Step through matches while typing
+            Some(Action::NextMatch) => {
+                self.jump_to_match(true);
+                true
+            }
+            Some(Action::PrevMatch) => {
+                self.jump_to_match(false);
+                true
+            }
+
+            _ => match key.bare_key {
+                BareKey::Backspace if key.has_no_modifiers() => {
+                    self.search_query.pop();
+                    self.update_search();
+                    true
+                }
+                BareKey::Char(c) if key.has_no_modifiers() => {
+                    self.search_query.push(c);
+                    self.update_search();
+                    true
+                }
+                _ => false,
+            },
+        }
+    }
+
+    fn handle_reference_picker_key(&mut self, key: KeyWithModifier) -> bool {
+        let count = self.current_item_references().len();
+        match self.keymap.action(Context::References, &key) {
+            Some(Action::Up) => self.reference_index = self.reference_index.saturating_sub(1),
+            Some(Action::Down) => self.reference_index = (self.reference_index + 1).min(count.saturating_sub(1)),
+            Some(Action::Pick) => {
+                if let Some(reference) = self.current_item_references().get(self.reference_index) {
+                    self.open_reference(reference);
+                }
+                self.mode = Mode::Normal;
+            }
+            Some(Action::Back) => self.mode = Mode::Normal,
+            _ => return false,
+        }
+        true
+    }
+
+    fn handle_code_comments_key(&mut self, key: KeyWithModifier) -> bool {
+        match self.keymap.action(Context::CodeComments, &key) {
+            Some(Action::Up) => self.code_comment_index = self.code_comment_index.saturating_sub(1),
+            Some(Action::Down) => {
+                self.code_comment_index = (self.code_comment_index + 1).min(self.code_comments.len().saturating_sub(1));
+            }
+            Some(Action::Pick) => {
+                if let Some(comment) = self.code_comments.get(self.code_comment_index) {
+                    self.open_reference(&reference::Reference {
+                        path: comment.path.clone(),
+                        line: Some(comment.line),
+                        column: None,
+                    });
+                }
+            }
+            Some(Action::Promote) => self.promote_code_comment(),
+            Some(Action::Retry) => self.start_code_scan(),
+            Some(Action::Back) => self.mode = Mode::Normal,
+            _ => return false,
+        }
+        true
+    }
+
+    fn handle_list_picker_key(&mut self, key: KeyWithModifier) -> bool {
+        match self.keymap.action(Context::Lists, &key) {
+            Some(Action::Up) => {
+                self.list_picker_index = self.list_picker_index.saturating_sub(1);
+                true
+            }
+            Some(Action::Down) => {
+                self.list_picker_index = (self.list_picker_index + 1).min(self.lists.len().saturating_sub(1));
+                true
+            }
+
+            // Switch to the list, or move the item there
+            Some(Action::Pick) => {
+                self.pick_list();
+                true
+            }
+
+            Some(Action::NewList) => {
+                self.start_list_name(false);
+                true
+            }
+            Some(Action::RenameList) => {
+                self.start_list_name(true);
+                true
+            }
+            Some(Action::DeleteList) => {
+                self.delete_picked_list();
+                true
+            }
+
+            Some(Action::Back) => {
+                self.moving_item_id = None;
+                self.mode = Mode::Normal;
+                true
+            }
+
+            _ => false,
+        }
+    }
+
+    fn handle_list_name_key(&mut self, key: KeyWithModifier) -> bool {
+        match self.keymap.action(Context::ListName, &key) {
+            Some(Action::Save) => {
+                self.save_list_name();
+                true
+            }
+
+            // Back to the picker without changes
+            Some(Action::Cancel) => {
+                self.renaming_list = None;
+                self.edit_buffer.clear();
+                self.mode = Mode::Lists;
+                true
+            }
+
+            _ => self.edit_buffer.handle_key(&key),
+        }
+    }
+
+    fn handle_details_key(&mut self, key: KeyWithModifier) -> bool {
+        match self.keymap.action(Context::Details, &key) {
+            Some(Action::Up) => {
+                self.details_scroll = self.details_scroll.saturating_sub(1);
+                true
+            }
+            Some(Action::Down) => {
+                let (header, notes) = self.details_lines();
+                let body_rows = self.rows.saturating_sub(header.len() + 2);
+                self.details_scroll = (self.details_scroll + 1).min(notes.len().saturating_sub(body_rows));
+                true
+            }
+
+            Some(Action::EditNotes) => {
+                self.start_editing_notes();
+                true
+            }
+
+            Some(Action::Back) => {
+                self.mode = Mode::Normal;
+                true
+            }
+
+            _ => false,
+        }
+    }
+
+    fn handle_notes_key(&mut self, key: KeyWithModifier) -> bool {
+        match self.keymap.action(Context::Notes, &key) {
+            // Enter starts a new line, so Esc is what finishes
+            Some(Action::Save) => {
+                self.save_notes();
+                true
+            }
+
+            // Paste with Ctrl+V
+            Some(Action::Paste) => {
+                // Handled via CustomMessage event, like in edit mode
+                true
+            }
+
+            // Typing, cursor movement, new lines and deletion
+            _ => self.notes_editor.handle_key(&key),
+        }
+    }
+
+    fn start_search(&mut self) {
+        self.grabbed_item_id = None;
+        self.search_query.clear();
+        self.search_origin = self.selected_index;
+        self.mode = Mode::Search;
+    }
+
+    fn update_search(&mut self) {
+        // Incremental search: select the first match at or after where the search started
+        let visible = self.visible_indices();
+        let count = visible.len();
+        let first_match = (0..count)
+            .map(|offset| (self.search_origin + offset) % count)
+            .find(|&view_idx| self.item_matches_search(&self.items[visible[view_idx]]));
+
+        self.selected_index = first_match.unwrap_or(self.search_origin);
+        self.clamp_selection();
+    }
+
+    fn jump_to_match(&mut self, forward: bool) {
+        // Wraps around the list like vim's n/N
+        let visible = self.visible_indices();
+        let count = visible.len();
+        if self.search_query.is_empty() || count == 0 {
+            return;
+        }
+
+        let next_match = (1..=count)
+            .map(|offset| {
+                if forward {
+                    (self.selected_index + offset) % count
+                } else {
+                    (self.selected_index + count * 2 - offset) % count
+                }
+            })
+            .find(|&view_idx| self.item_matches_search(&self.items[visible[view_idx]]));
+
+        if let Some(view_idx) = next_match {
+            self.selected_index = view_idx;
+        }
+    }
+
+    fn item_matches_search(&self, item: &TodoItem) -> bool {
+        !self.search_spans(&item.text).is_empty()
+    }
+
+    fn search_spans(&self, text: &str) -> Vec<(usize, usize)> {
+        if self.search_query.is_empty() {
+            return Vec::new();
+        }
+
+        let case_sensitive = match self.search_case {
+            SearchCase::Smart => self.search_query.chars().any(char::is_uppercase),
+            SearchCase::Insensitive => false,
+            SearchCase::Sensitive => true,
+        };
+        find_matches(text, &self.search_query, case_sensitive)
+    }
+
+    fn search_match_position(&self) -> (usize, usize) {
+        // 1-based position of the selected item among all matching items, and the match count
+        let visible = self.visible_indices();
+        let matching: Vec<usize> = (0..visible.len())
+            .filter(|&view_idx| self.item_matches_search(&self.items[visible[view_idx]]))
+            .collect();
+        let position = matching
+            .iter()
+            .position(|&view_idx| view_idx == self.selected_index)
+            .map(|position| position + 1)
+            .unwrap_or(0);
+        (position, matching.len())
+    }
+
+    fn toggle_current_item(&mut self) {
+        if let Some(idx) = self.selected_item_index() {
+            let before = self.snapshot();
+            let original_cursor_position = self.selected_index;
+            self.items[idx].done = !self.items[idx].done;
+            self.sort_items();
+            
+            // Keep cursor at the same visual position instead of following the moved item
+            self.selected_index = std::cmp::min(original_cursor_position, self.visible_indices().len().saturating_sub(1));
+            
+            self.record_change(before);
+            self.save_todos();
+        }
+    }
+
+    fn change_current_priority(&mut self, change: fn(Priority) -> Priority) {
+        if let Some(idx) = self.selected_item_index() {
+            let new_priority = change(self.items[idx].priority);
+            if new_priority != self.items[idx].priority {
+                let before = self.snapshot();
+                self.items[idx].priority = new_priority;
+                self.sort_items();
+                self.record_change(before);
+                self.save_todos();
+            }
+        }
+    }
+
+    fn toggle_grab(&mut self) {
+        if let Some(grabbed_id) = self.grabbed_item_id {
+            // Release the grabbed item
+            if grabbed_id == self.get_current_item_id() {
+                self.grabbed_item_id = None;
+            }
+        } else {
+            // Grab the current item
+            self.grabbed_item_id = Some(self.get_current_item_id());
+            self.grab_recorded = false;
+        }
+    }
+
+    fn get_current_item_id(&self) -> usize {
+        self.selected_item_index().map(|idx| self.items[idx].id).unwrap_or(0)
+    }
+
+    fn selected_item_index(&self) -> Option<usize> {
+        self.visible_indices().get(self.selected_index).copied()
+    }
+
+    fn select_item_by_id(&mut self, id: usize) {
+        if let Some(pos) = self.visible_indices().iter().position(|&idx| self.items[idx].id == id) {
+            self.selected_index = pos;
+        }
+    }
+
+    fn clamp_selection(&mut self) {
+        let visible_count = self.visible_indices().len();
+        if self.selected_index >= visible_count {
+            self.selected_index = visible_count.saturating_sub(1);
+        }
+    }
+
+    fn item_depths(&self) -> Vec<usize> {
+        // Items are kept in tree order, so every parent has been seen before its children
+        let mut depth_by_id: HashMap<usize, usize> = HashMap::new();
+        self.items
+            .iter()
+            .map(|item| {
+                let depth = item
+                    .parent
+                    .and_then(|parent_id| depth_by_id.get(&parent_id))
+                    .map(|parent_depth| parent_depth + 1)
+                    .unwrap_or(0);
+                depth_by_id.insert(item.id, depth);
+                depth
+            })
+            .collect()
+    }
+
+    fn visible_indices(&self) -> Vec<usize> {
+        // Skip everything below a collapsed item until we are back at its depth
+        let depths = self.item_depths();
+        let in_filter = self.tag_filter_matches();
+        let mut visible = Vec::with_capacity(self.items.len());
+        let mut collapsed_depth: Option<usize> = None;
+
+        for (idx, item) in self.items.iter().enumerate() {
+            if let Some(depth) = collapsed_depth {
+                if depths[idx] > depth {
+                    continue;
+                }
+                collapsed_depth = None;
+            }
+            if !in_filter[idx] || item.list != self.active_list {
+                continue;
+            }
+            visible.push(idx);
+            if item.collapsed {
+                collapsed_depth = Some(depths[idx]);
+            }
+        }
+
+        visible
+    }
+
+    fn tag_filter_matches(&self) -> Vec<bool> {
+        let tag = match &self.tag_filter {
+            Some(tag) => tag,
+            None => return vec![true; self.items.len()],
+        };
+
+        // Items match when they or one of their ancestors carry the tag; new items being typed
+        // in are empty and always match
+        let mut matches_by_id: HashMap<usize, bool> = HashMap::new();
+        let mut matches: Vec<bool> = self
+            .items
+            .iter()
+            .map(|item| {
+                let inherited = item.parent.and_then(|parent_id| matches_by_id.get(&parent_id)).copied().unwrap_or(false);
+                let matched = inherited || item.tags.contains(tag) || item.text.is_empty();
+                matches_by_id.insert(item.id, matched);
+                matched
+            })
+            .collect();
+
+        // Ancestors of matching items stay visible for context
+        let mut context_ids: HashSet<usize> = HashSet::new();
+        for (idx, item) in self.items.iter().enumerate().rev() {
+            if matches[idx] || context_ids.contains(&item.id) {
+                matches[idx] = true;
+                if let Some(parent_id) = item.parent {
+                    context_ids.insert(parent_id);
+                }
+            }
+        }
+
+        matches
+    }
+
+    fn known_tags(&self) -> Vec<String> {
+        let tags: std::collections::BTreeSet<&String> = self
+            .items
+            .iter()
+            .filter(|item| item.list == self.active_list)
+            .flat_map(|item| item.tags.iter())
+            .collect();
+        tags.into_iter().cloned().collect()
+    }
+
+    fn cycle_tag_filter(&mut self) {
+        let known_tags = self.known_tags();
+        let next_tag = match &self.tag_filter {
+            None => known_tags.first().cloned(),
+            Some(current) => known_tags.iter().find(|tag| *tag > current).cloned(),
+        };
+        self.set_tag_filter(next_tag);
+    }
+
+    fn set_tag_filter(&mut self, tag: Option<String>) {
+        let current_id = self.selected_item_index().map(|idx| self.items[idx].id);
+        self.tag_filter = tag;
+        self.grabbed_item_id = None;
+
+        match current_id.filter(|id| self.visible_indices().iter().any(|&idx| self.items[idx].id == *id)) {
+            Some(id) => self.select_item_by_id(id),
+            None => self.selected_index = 0,
+        }
+    }
+
+    fn with_filter_tag(&self, text: String, parent: Option<usize>) -> String {
+        // Append the active filter tag unless the text or an ancestor already carries it
+        let tag = match &self.tag_filter {
+            Some(tag) => tag,
+            None => return text,
+        };
+
+        let mut ancestor_id = parent;
+        while let Some(id) = ancestor_id {
+            match self.items.iter().find(|item| item.id == id) {
+                Some(ancestor) if ancestor.tags.contains(tag) => return text,
+                Some(ancestor) => ancestor_id = ancestor.parent,
+                None => break,
+            }
+        }
+
+        if parse_tags(&text).contains(tag) {
+            text
+        } else {
+            format!("{} #{}", text, tag)
+        }
+    }
+
+    fn list_sorts_by_priority(&self, name: &str) -> bool {
+        // Lists without their own sort mode follow the `sort` configuration
+        self.lists
+            .iter()
+            .find(|list| list.name == name)
+            .and_then(|list| list.sort_by_priority)
+            .unwrap_or(self.sort_by_priority)
+    }
+
+    fn active_list_position(&self) -> usize {
+        self.lists.iter().position(|list| list.name == self.active_list).unwrap_or(0)
+    }
+
+    fn normalize_lists(&mut self) {
+        // Every item belongs to a known list, there is always at least one list, and the active
+        // list exists
+        let first_list = self.lists.first().map(|list| list.name.clone()).unwrap_or_else(|| schema::DEFAULT_LIST.to_string());
+        for item in self.items.iter_mut() {
+            if item.list.is_empty() {
+                item.list = first_list.clone();
+            }
+            if !self.lists.iter().any(|list| list.name == item.list) {
+                self.lists.push(ListMeta {
+                    name: item.list.clone(),
+                    ..Default::default()
+                });
+            }
+        }
+        if self.lists.is_empty() {
+            self.lists.push(ListMeta {
+                name: first_list,
+                ..Default::default()
+            });
+        }
+        if !self.lists.iter().any(|list| list.name == self.active_list) {
+            self.switch_list(self.lists[0].name.clone());
+        }
+    }
+
+    fn switch_list(&mut self, name: String) {
+        self.active_list = name;
+        self.grabbed_item_id = None;
+        self.selected_index = 0;
+        // A filter from the old list could hide everything in the new one
+        self.tag_filter = None;
+        self.search_query.clear();
+    }
+
+    fn cycle_list(&mut self, forward: bool) {
+        let count = self.lists.len();
+        let position = self.active_list_position();
+        let next = if forward { (position + 1) % count } else { (position + count - 1) % count };
+        self.switch_list(self.lists[next].name.clone());
+    }
+
+    fn open_list_picker(&mut self, moving: bool) {
+        self.moving_item_id = None;
+        if moving {
+            match self.selected_item_index() {
+                Some(idx) => self.moving_item_id = Some(self.items[idx].id),
+                None => return,
+            }
+        }
+        self.grabbed_item_id = None;
+        self.list_picker_index = self.active_list_position();
+        self.mode = Mode::Lists;
+    }
+
+    fn pick_list(&mut self) {
+        let name = match self.lists.get(self.list_picker_index) {
+            Some(list) => list.name.clone(),
+            None => return,
+        };
+        self.mode = Mode::Normal;
+        match self.moving_item_id.take() {
+            Some(id) => self.move_item_to_list(id, name),
+            None => self.switch_list(name),
+        }
+    }
+
+    fn move_item_to_list(&mut self, id: usize, name: String) {
+        let idx = match self.items.iter().position(|item| item.id == id) {
+            Some(idx) if self.items[idx].list != name => idx,
+            _ => return,
+        };
+        let before = self.snapshot();
+
+        // Subtasks move along, and the item lands at the end of the other list's top level
+        let end = self.subtree_end(idx);
+        for item in &mut self.items[idx..end] {
+            item.list = name.clone();
+        }
+        self.items[idx].parent = None;
+        self.items[idx].display_order = self.next_display_order;
+        self.next_display_order += 1;
+
+        self.sort_items();
+        self.clamp_selection();
+        self.notice = Some(Notice::Info(format!("Moved to {}", name)));
+        self.record_change(before);
+        self.save_todos();
+    }
+
+    fn start_list_name(&mut self, rename: bool) {
+        self.renaming_list = if rename {
+            self.lists.get(self.list_picker_index).map(|list| list.name.clone())
+        } else {
+            None
+        };
+        self.edit_buffer = LineEditor::new(self.renaming_list.clone().unwrap_or_default());
+        self.mode = Mode::ListName;
+    }
+
+    fn save_list_name(&mut self) {
+        let name = self.edit_buffer.text().trim().to_string();
+        if name.is_empty() {
+            return;
+        }
+        if self.lists.iter().any(|list| list.name == name) && self.renaming_list.as_ref() != Some(&name) {
+            self.notice = Some(Notice::Info(format!("There is already a list named {}", name)));
+            return;
+        }
+
+        let before = self.snapshot();
+        match self.renaming_list.take() {
+            Some(old_name) => {
+                for list in self.lists.iter_mut().filter(|list| list.name == old_name) {
+                    list.name = name.clone();
+                }
+                for item in self.items.iter_mut().filter(|item| item.list == old_name) {
+                    item.list = name.clone();
+                }
+                if self.active_list == old_name {
+                    self.active_list = name;
+                }
+            }
+            None => {
+                self.lists.push(ListMeta {
+                    name,
+                    ..Default::default()
+                });
+                self.list_picker_index = self.lists.len() - 1;
+            }
+        }
+
+        self.edit_buffer.clear();
+        self.mode = Mode::Lists;
+        self.record_change(before);
+        self.save_todos();
+    }
+
+    fn delete_picked_list(&mut self) {
+        let name = match self.lists.get(self.list_picker_index) {
+            Some(list) => list.name.clone(),
+            None => return,
+        };
+        if self.lists.len() == 1 {
+            self.notice = Some(Notice::Info("Can't delete the only list".to_string()));
+            return;
+        }
+        if self.items.iter().any(|item| item.list == name) {
+            self.notice = Some(Notice::Info(format!("Move or delete the items in {} first", name)));
+            return;
+        }
+
+        let before = self.snapshot();
+        self.lists.remove(self.list_picker_index);
+        self.list_picker_index = self.list_picker_index.min(self.lists.len() - 1);
+        self.normalize_lists();
+        self.record_change(before);
+        self.save_todos();
+    }
+
+    fn subtree_end(&self, idx: usize) -> usize {
+        // Exclusive end of the contiguous run holding the item and its descendants
+        let depths = self.item_depths();
+        (idx + 1..self.items.len())
+            .find(|&other| depths[other] <= depths[idx])
+            .unwrap_or(self.items.len())
+    }
+
+    fn has_children(&self, id: usize) -> bool {
+        self.items.iter().any(|item| item.parent == Some(id))
+    }
+
+    fn collapse_current_item(&mut self) {
+        if let Some(idx) = self.selected_item_index() {
+            let item_id = self.items[idx].id;
+            if self.has_children(item_id) && !self.items[idx].collapsed {
+                self.items[idx].collapsed = true;
+                self.save_todos();
+            } else if let Some(parent_id) = self.items[idx].parent {
+                self.select_item_by_id(parent_id);
+            }
+        }
+    }
+
+    fn expand_current_item(&mut self) {
+        if let Some(idx) = self.selected_item_index() {
+            if self.items[idx].collapsed {
+                self.items[idx].collapsed = false;
+                self.save_todos();
+            }
+        }
+    }
+
+    fn indent_current_item(&mut self) {
+        if let Some(idx) = self.selected_item_index() {
+            let parent = self.items[idx].parent;
+            let list = &self.items[idx].list;
+
+            // The new parent is the nearest sibling above in tree order
+            let new_parent_id = self.items[..idx]
+                .iter()
+                .rev()
+                .find(|item| item.parent == parent && item.list == *list)
+                .map(|item| item.id);
+
+            if let Some(new_parent_id) = new_parent_id {
+                let before = self.snapshot();
+
+                // Become the last child of the new parent
+                self.items[idx].parent = Some(new_parent_id);
+                self.items[idx].display_order = self.next_display_order;
+                self.next_display_order += 1;
+
+                if let Some(new_parent) = self.items.iter_mut().find(|item| item.id == new_parent_id) {
+                    new_parent.collapsed = false;
+                }
+
+                self.sort_items();
+                self.record_change(before);
+                self.save_todos();
+            }
+        }
+    }
+
+    fn outdent_current_item(&mut self) {
+        if let Some(idx) = self.selected_item_index() {
+            let parent = self
+                .items[idx]
+                .parent
+                .and_then(|parent_id| self.items.iter().find(|item| item.id == parent_id))
+                .map(|parent_item| (parent_item.parent, parent_item.display_order));
+
+            if let Some((grandparent, parent_order)) = parent {
+                let before = self.snapshot();
+
+                // Slot in directly after the old parent among its siblings
+                let item_id = self.items[idx].id;
+                for item in self.items.iter_mut() {
+                    if item.display_order > parent_order && item.id != item_id {
+                        item.display_order += 1;
+                    }
+                }
+                self.items[idx].parent = grandparent;
+                self.items[idx].display_order = parent_order + 1;
+                self.next_display_order = self
+                    .items
+                    .iter()
+                    .map(|item| item.display_order)
+                    .max()
+                    .unwrap_or(0)
+                    + 1;
+
+                self.sort_items();
+                self.record_change(before);
+                self.save_todos();
+            }
+        }
+    }
+
+    fn sort_group(&self, item: &TodoItem) -> (bool, Priority) {
+        // Items that sort_items keeps next to each other; swapping within a group is always visible
+        let priority = if self.list_sorts_by_priority(&item.list) { item.priority } else { Priority::None };
+        (item.done, priority)
+    }
+
+    fn move_grabbed_item_up(&mut self) {
+        if let Some(grabbed_id) = self.grabbed_item_id {
+            let grabbed_item = self.items.iter().find(|item| item.id == grabbed_id);
+            let grabbed_group = grabbed_item.map(|item| self.sort_group(item));
+            let grabbed_parent = grabbed_item.and_then(|item| item.parent);
+            let grabbed_list = grabbed_item.map(|item| item.list.clone()).unwrap_or_default();
+            let visible_ids: HashSet<usize> = self.visible_indices().into_iter().map(|idx| self.items[idx].id).collect();
+
+            // Siblings hidden by the tag filter are stepped over; stay put if none are visible above
+            let has_visible_sibling_above = grabbed_item.is_some_and(|grabbed| {
+                self.items.iter().any(|item| {
+                    item.parent == grabbed_parent && item.display_order < grabbed.display_order && visible_ids.contains(&item.id)
+                })
+            });
+            if !has_visible_sibling_above {
+                return;
+            }
+            let before = self.snapshot();
             
             loop {
-                // Work with logical ordering (by display_order) - always move by 1 position regardless of status
-                let mut logical_items: Vec<_> = self.items.iter().enumerate().collect();
-                logical_items.sort_by(|a, b| a.1.display_order.cmp(&b.1.display_order));
+                // Work with logical ordering (by display_order) among siblings - the subtree follows its root
+                let mut logical_items: Vec<_> = self
+                    .items
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, item)| item.parent == grabbed_parent && item.list == grabbed_list)
+                    .collect();
+                logical_items.sort_by_key(|(_, item)| item.display_order);
                 
                 if let Some(logical_pos) = logical_items.iter().position(|(_, item)| item.id == grabbed_id) {
                     if logical_pos > 0 {
-                        // Always swap with the item directly above in logical order
+                        // Always swap with the sibling directly above in logical order
                         let current_item_idx = logical_items[logical_pos].0;
                         let target_item_idx = logical_items[logical_pos - 1].0;
-                        let target_item_done = self.items[target_item_idx].done;
+                        let target_group = Some(self.sort_group(&self.items[target_item_idx]));
+                        let target_item_id = self.items[target_item_idx].id;
                         
                         let temp_display_order = self.items[current_item_idx].display_order;
                         self.items[current_item_idx].display_order = self.items[target_item_idx].display_order;
@@ -329,11 +2033,11 @@ impl State {
                         // Re-sort to reflect the new logical order
                         self.sort_items();
                         
-                        // If we swapped with an item of the same status, we're done (visual change occurred)
-                        if grabbed_item_done == target_item_done {
+                        // If we swapped with a visible item in the same group, we're done (visual change occurred)
+                        if grabbed_group == target_group && visible_ids.contains(&target_item_id) {
                             break;
                         }
-                        // Otherwise, continue moving up (crossed over a done item)
+                        // Otherwise, continue moving up (crossed over a done or differently prioritized item)
                     } else {
                         break; // Can't move up anymore
                     }
@@ -341,318 +2045,1411 @@ impl State {
                     break; // Item not found
                 }
             }
+
+            // A whole grab session is undone in one step
+            if !self.grab_recorded {
+                self.grab_recorded = self.record_change(before);
+            }
+            self.save_todos();
+        }
+    }
+
+    fn move_grabbed_item_down(&mut self) {
+        if let Some(grabbed_id) = self.grabbed_item_id {
+            let grabbed_item = self.items.iter().find(|item| item.id == grabbed_id);
+            let grabbed_group = grabbed_item.map(|item| self.sort_group(item));
+            let grabbed_parent = grabbed_item.and_then(|item| item.parent);
+            let grabbed_list = grabbed_item.map(|item| item.list.clone()).unwrap_or_default();
+            let visible_ids: HashSet<usize> = self.visible_indices().into_iter().map(|idx| self.items[idx].id).collect();
+
+            // Siblings hidden by the tag filter are stepped over; stay put if none are visible below
+            let has_visible_sibling_below = grabbed_item.is_some_and(|grabbed| {
+                self.items.iter().any(|item| {
+                    item.parent == grabbed_parent && item.display_order > grabbed.display_order && visible_ids.contains(&item.id)
+                })
+            });
+            if !has_visible_sibling_below {
+                return;
+            }
+            let before = self.snapshot();
+            
+            loop {
+                // Work with logical ordering (by display_order) among siblings - the subtree follows its root
+                let mut logical_items: Vec<_> = self
+                    .items
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, item)| item.parent == grabbed_parent && item.list == grabbed_list)
+                    .collect();
+                logical_items.sort_by_key(|(_, item)| item.display_order);
+                
+                if let Some(logical_pos) = logical_items.iter().position(|(_, item)| item.id == grabbed_id) {
+                    if logical_pos < logical_items.len() - 1 {
+                        // Always swap with the sibling directly below in logical order
+                        let current_item_idx = logical_items[logical_pos].0;
+                        let target_item_idx = logical_items[logical_pos + 1].0;
+                        let target_group = Some(self.sort_group(&self.items[target_item_idx]));
+                        let target_item_id = self.items[target_item_idx].id;
+                        
+                        let temp_display_order = self.items[current_item_idx].display_order;
+                        self.items[current_item_idx].display_order = self.items[target_item_idx].display_order;
+                        self.items[target_item_idx].display_order = temp_display_order;
+                        
+                        // Re-sort to reflect the new logical order
+                        self.sort_items();
+                        
+                        // If we swapped with a visible item in the same group, we're done (visual change occurred)
+                        if grabbed_group == target_group && visible_ids.contains(&target_item_id) {
+                            break;
+                        }
+                        // Otherwise, continue moving down (crossed over a done or differently prioritized item)
+                    } else {
+                        break; // Can't move down anymore
+                    }
+                } else {
+                    break; // Item not found
+                }
+            }
+
+            // A whole grab session is undone in one step
+            if !self.grab_recorded {
+                self.grab_recorded = self.record_change(before);
+            }
+            self.save_todos();
+        }
+    }
+
+    fn add_new_item(&mut self) {
+        // Adding and typing the text undo together
+        self.pending_edit = Some(self.snapshot());
+
+        let new_id = self.next_id;
+        self.next_id += 1;
+
+        let (parent, new_display_order) = match self.selected_item_index() {
+            None => (None, self.next_display_order),
+            Some(current_idx) => {
+                let current_item = &self.items[current_idx];
+                let parent = current_item.parent;
+
+                let target_order = if current_item.done {
+                    // Cursor is on completed item - snap to end of the todo siblings
+                    self.items
+                        .iter()
+                        .filter(|item| item.parent == parent && item.list == current_item.list && !item.done)
+                        .map(|item| item.display_order + 1)
+                        .max()
+                        .unwrap_or(current_item.display_order)
+                } else {
+                    // Cursor is on todo item - insert above current position
+                    current_item.display_order
+                };
+
+                // Shift every item at or after the target slot down by 1
+                for item in self.items.iter_mut() {
+                    if item.display_order >= target_order {
+                        item.display_order += 1;
+                    }
+                }
+
+                (parent, target_order)
+            }
+        };
+
+        let new_item = TodoItem {
+            text: String::new(),
+            done: false,
+            id: new_id,
+            display_order: new_display_order,
+            list: self.active_list.clone(),
+            parent,
+            ..Default::default()
+        };
+
+        self.items.push(new_item);
+        self.sort_items();
+        self.select_item_by_id(new_id);
+        self.next_display_order = self
+            .items
+            .iter()
+            .map(|item| item.display_order)
+            .max()
+            .unwrap_or(0)
+            + 1;
+
+        self.start_editing_current();
+    }
+
+    fn start_editing_current(&mut self) {
+        if let Some(idx) = self.selected_item_index() {
+            let item = &self.items[idx];
+            // Due dates are edited as part of the text, so deleting the token clears them
+            self.edit_buffer = LineEditor::new(match item.due {
+                Some(due) => format!("{} due:{}", item.text, due),
+                None => item.text.clone(),
+            });
+            if self.pending_edit.is_none() {
+                self.pending_edit = Some(self.snapshot());
+            }
+            self.mode = Mode::Edit;
+        }
+    }
+
+    fn save_edit(&mut self) {
+        if let Some(idx) = self.selected_item_index() {
+            let was_new = self.items[idx].text.is_empty();
+            let (mut text, due) = self.extract_due(self.edit_buffer.text());
+            if was_new && !text.is_empty() {
+                // Keep items added while filtering inside the filter
+                text = self.with_filter_tag(text, self.items[idx].parent);
+            }
+            self.items[idx].tags = parse_tags(&text);
+            self.items[idx].text = text;
+            self.items[idx].due = due;
+            let pasted_notes = std::mem::take(&mut self.pasted_notes);
+            if !pasted_notes.is_empty() {
+                let notes = &mut self.items[idx].notes;
+                *notes = if notes.is_empty() { pasted_notes } else { format!("{}\n{}", notes, pasted_notes) };
+            }
+            if self.items[idx].text.is_empty() {
+                // Remove empty items, but not the subtasks of one whose text was cleared
+                self.remove_keeping_children(idx);
+            }
+            if let Some(before) = self.pending_edit.take() {
+                self.record_change(before);
+            }
+            self.save_todos();
+        }
+        self.pending_edit = None;
+        self.mode = Mode::Normal;
+        self.edit_buffer.clear();
+    }
+
+    fn start_editing_notes(&mut self) {
+        if let Some(idx) = self.selected_item_index() {
+            self.notes_editor = NotesEditor::new(&self.items[idx].notes);
+            self.pending_edit = Some(self.snapshot());
+            self.mode = Mode::Notes;
+        }
+    }
+
+    fn save_notes(&mut self) {
+        if let Some(idx) = self.selected_item_index() {
+            self.items[idx].notes = self.notes_editor.text();
+            if let Some(before) = self.pending_edit.take() {
+                self.record_change(before);
+            }
+            self.save_todos();
+        }
+        self.pending_edit = None;
+        self.mode = Mode::Details;
+    }
+
+    fn extract_due(&self, text: &str) -> (String, Option<Date>) {
+        // Pull recognized `due:` words out of the text; unrecognized ones stay visible
+        let mut due = None;
+        let words: Vec<&str> = text
+            .split_whitespace()
+            .filter(|word| {
+                match word.strip_prefix("due:").and_then(|value| date::parse_due(value, self.today)) {
+                    Some(date) => {
+                        due = Some(date);
+                        false
+                    }
+                    None => true,
+                }
+            })
+            .collect();
+
+        if due.is_some() {
+            (words.join(" "), due)
+        } else {
+            (text.trim().to_string(), None)
+        }
+    }
+
+    fn cancel_edit(&mut self) {
+        // If this was a new empty item, remove it
+        if let Some(idx) = self.selected_item_index() {
+            if self.items[idx].text.is_empty() {
+                self.remove_subtree(idx);
+            }
+            // If editing existing item, revert any changes by doing nothing
+            // The original text remains unchanged
+        }
+        self.pasted_notes.clear();
+        self.pending_edit = None;
+        self.mode = Mode::Normal;
+        self.edit_buffer.clear();
+    }
+
+    fn delete_current_item(&mut self) {
+        if let Some(idx) = self.selected_item_index() {
+            let before = self.snapshot();
+            self.remove_subtree(idx);
+            self.record_change(before);
             self.save_todos();
         }
     }
 
-    fn move_grabbed_item_down(&mut self) {
-        if let Some(grabbed_id) = self.grabbed_item_id {
-            let grabbed_item_done = self.items.iter().find(|item| item.id == grabbed_id).map(|item| item.done).unwrap_or(false);
-            
-            loop {
-                // Work with logical ordering (by display_order) - always move by 1 position regardless of status
-                let mut logical_items: Vec<_> = self.items.iter().enumerate().collect();
-                logical_items.sort_by(|a, b| a.1.display_order.cmp(&b.1.display_order));
-                
-                if let Some(logical_pos) = logical_items.iter().position(|(_, item)| item.id == grabbed_id) {
-                    if logical_pos < logical_items.len() - 1 {
-                        // Always swap with the item directly below in logical order
-                        let current_item_idx = logical_items[logical_pos].0;
-                        let target_item_idx = logical_items[logical_pos + 1].0;
-                        let target_item_done = self.items[target_item_idx].done;
-                        
-                        let temp_display_order = self.items[current_item_idx].display_order;
-                        self.items[current_item_idx].display_order = self.items[target_item_idx].display_order;
-                        self.items[target_item_idx].display_order = temp_display_order;
-                        
-                        // Re-sort to reflect the new logical order
-                        self.sort_items();
-                        
-                        // If we swapped with an item of the same status, we're done (visual change occurred)
-                        if grabbed_item_done == target_item_done {
-                            break;
-                        }
-                        // Otherwise, continue moving down (crossed over a done item)
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            items: self.items.clone(),
+            lists: self.lists.clone(),
+            next_id: self.next_id,
+            next_display_order: self.next_display_order,
+            selected_id: self.selected_item_index().map(|idx| self.items[idx].id),
+        }
+    }
+
+    fn record_change(&mut self, before: Snapshot) -> bool {
+        // Push the state from before a mutation onto the undo stack, unless nothing changed
+        self.grab_recorded = false;
+        if before.items == self.items && before.lists == self.lists {
+            return false;
+        }
+
+        self.history.undo.push(before);
+        if self.history.undo.len() > UNDO_LIMIT {
+            self.history.undo.remove(0);
+        }
+        self.history.redo.clear();
+        true
+    }
+
+    fn undo(&mut self) {
+        if let Some(snapshot) = self.history.undo.pop() {
+            let current = self.snapshot();
+            self.history.redo.push(current);
+            self.restore_snapshot(snapshot);
+        }
+    }
+
+    fn redo(&mut self) {
+        if let Some(snapshot) = self.history.redo.pop() {
+            let current = self.snapshot();
+            self.history.undo.push(current);
+            self.restore_snapshot(snapshot);
+        }
+    }
+
+    fn restore_snapshot(&mut self, snapshot: Snapshot) {
+        self.items = snapshot.items;
+        // Histories saved before there were several lists don't carry them
+        if !snapshot.lists.is_empty() {
+            self.lists = snapshot.lists;
+        }
+        self.normalize_lists();
+        self.next_id = snapshot.next_id;
+        self.next_display_order = snapshot.next_display_order;
+        self.grabbed_item_id = None;
+        self.sort_items();
+
+        match snapshot.selected_id {
+            Some(id) => self.select_item_by_id(id),
+            None => self.selected_index = 0,
+        }
+        self.clamp_selection();
+        self.save_todos();
+    }
+
+    fn remove_subtree(&mut self, idx: usize) {
+        let end = self.subtree_end(idx);
+        self.items.drain(idx..end);
+        self.clamp_selection();
+    }
+
+    fn remove_keeping_children(&mut self, idx: usize) {
+        // The children move up a level and take the removed item's place among its siblings
+        let removed = self.items.remove(idx);
+        let mut children: Vec<usize> = (0..self.items.len()).filter(|&other| self.items[other].parent == Some(removed.id)).collect();
+        children.sort_by_key(|&child| self.items[child].display_order);
+
+        for item in self.items.iter_mut() {
+            if item.display_order > removed.display_order && item.parent != Some(removed.id) {
+                item.display_order += children.len();
+            }
+        }
+        for (offset, &child) in children.iter().enumerate() {
+            self.items[child].parent = removed.parent;
+            self.items[child].display_order = removed.display_order + offset;
+        }
+        self.next_display_order = self.items.iter().map(|item| item.display_order).max().unwrap_or(0) + 1;
+
+        self.sort_items();
+        self.clamp_selection();
+    }
+
+    fn sort_items(&mut self) {
+        // Todo items first (by display_order), then done items (by display_order), with each
+        // item's children placed directly after it using the same rule. In priority sort mode,
+        // items of the same status are grouped by priority before display_order.
+        let current_id = self.get_current_item_id();
+
+        // Lists are kept apart in the order they were created, each with its own sort mode
+        let list_order: HashMap<String, (usize, bool)> = self
+            .lists
+            .iter()
+            .enumerate()
+            .map(|(position, list)| (list.name.clone(), (position, self.list_sorts_by_priority(&list.name))))
+            .collect();
+        self.items.sort_by(|a, b| {
+            let (a_list, sort_by_priority) = list_order.get(&a.list).copied().unwrap_or((usize::MAX, false));
+            let (b_list, _) = list_order.get(&b.list).copied().unwrap_or((usize::MAX, false));
+            match (a.done, b.done) {
+                _ if a_list != b_list => a_list.cmp(&b_list),
+                (false, true) => std::cmp::Ordering::Less,  // Todo items come first
+                (true, false) => std::cmp::Ordering::Greater, // Done items come last
+                // Optionally group by priority (highest first) within the same status
+                _ if sort_by_priority && a.priority != b.priority => b.priority.cmp(&a.priority),
+                _ => a.display_order.cmp(&b.display_order),   // Within same status, sort by original order
+            }
+        });
+
+        // Items pointing at a missing parent, a parent in another list, or themselves become top-level
+        let list_by_id: HashMap<usize, String> = self.items.iter().map(|item| (item.id, item.list.clone())).collect();
+        for item in self.items.iter_mut() {
+            if item.parent.is_some_and(|parent_id| parent_id == item.id || list_by_id.get(&parent_id) != Some(&item.list)) {
+                item.parent = None;
+            }
+        }
+
+        let mut placed = vec![false; self.items.len()];
+        let mut tree_order = Vec::with_capacity(self.items.len());
+        append_subtree(&self.items, None, &mut placed, &mut tree_order);
+
+        // Anything left over is part of a parent cycle - break it at the first item found
+        while let Some(idx) = placed.iter().position(|&is_placed| !is_placed) {
+            self.items[idx].parent = None;
+            placed[idx] = true;
+            tree_order.push(idx);
+            let root_id = self.items[idx].id;
+            append_subtree(&self.items, Some(root_id), &mut placed, &mut tree_order);
+        }
+
+        let mut slots: Vec<Option<TodoItem>> = std::mem::take(&mut self.items).into_iter().map(Some).collect();
+        self.items = tree_order.into_iter().filter_map(|idx| slots[idx].take()).collect();
+
+        // Update selected index to follow the moved item
+        self.select_item_by_id(current_id);
+    }
+
+
+    fn render_empty_state(&self) {
+        let message = match self.keymap.first_key_label(Context::Normal, Action::Add) {
+            Some(key) => format!("Press '{}' to add a todo", key),
+            None => "No todos yet".to_string(),
+        };
+        let message = self.truncate_text(&message, self.cols);
+        let y = self.rows / 2;
+        let x = (self.cols.saturating_sub(text::width(&message))) / 2;
+
+        print!("\x1b[{};{}H\x1b[2m{}\x1b[0m", y + 1, x + 1, message);
+        self.render_notice(self.rows);
+    }
+
+    fn render_notice(&self, row: usize) {
+        let (style, message) = match &self.notice {
+            Some(Notice::Info(message)) => ("\x1b[33m", message),
+            Some(Notice::Error(message)) => ("\x1b[1;31m", message),
+            None => return,
+        };
+        let message = self.truncate_text(message, self.cols);
+        print!("\x1b[{};1H\x1b[K{}{}\x1b[0m", row, style, message);
+    }
+
+    fn render_status_bar(&self, row: usize) {
+        // Mode, progress of the active list and the file in use on the left, key hints on the right
+        let context = self.key_context();
+        let (mode, mode_style) = match context {
+            Context::Grab => ("GRAB", "\x1b[1;30;43m"),
+            Context::Edit => ("EDIT", "\x1b[1;30;46m"),
+            Context::Search => ("SEARCH", "\x1b[1;30;45m"),
+            _ => ("NORMAL", "\x1b[1;7m"),
+        };
+        // The hints come from the keymap; with no items at all only a few keys do anything
+        let hints: Vec<String> = self
+            .keymap
+            .hints(context)
+            .into_iter()
+            .filter(|(action, _)| !self.items.is_empty() || !action.needs_item())
+            .map(|(_, hint)| hint)
+            .collect();
+
+        let in_list: Vec<&TodoItem> = self.items.iter().filter(|item| item.list == self.active_list).collect();
+        let done = in_list.iter().filter(|item| item.done).count();
+        let file = match &self.project_dir {
+            Some(_) => self.todo_file_label(),
+            None => self.filename.clone(),
+        };
+        let status = format!("{} {} \x1b[0m {}/{} done \x1b[2m· {}\x1b[0m", mode_style, mode, done, in_list.len(), file);
+
+        // As many hints as fit next to the status, most important first
+        let hint_room = self.cols.saturating_sub(text::width(&format!(" {}  {}/{} done · {}", mode, done, in_list.len(), file)) + 4);
+        let mut shown = Vec::new();
+        for hint in &hints {
+            shown.push(hint.as_str());
+            if text::width(&shown.join("  ")) > hint_room {
+                shown.pop();
+                break;
+            }
+        }
+        let hints = shown.join("  ");
+        let hints = if hints.is_empty() {
+            hints
+        } else {
+            format!("\x1b[{}G\x1b[2m{}\x1b[0m", self.cols - text::width(&hints) + 1, hints)
+        };
+        print!("\x1b[{};1H\x1b[K{}{}", row, text::clip(&status, self.cols), hints);
+    }
+
+    fn show_status_bar(&self) -> bool {
+        self.status_bar && self.rows >= STATUS_BAR_MIN_ROWS
+    }
+
+    fn render_todo_list(&self, layout: &ListLayout) {
+        let visible = &layout.visible;
+        let depths = &layout.depths;
+
+        // Load error banner takes the first rows
+        if let Some(load_error) = &self.load_error {
+            let source = match &load_error.backup_path {
+                Some(backup_path) => format!("Showing backup {} read-only.", backup_path),
+                None => "Read-only until resolved.".to_string(),
+            };
+            let action = if load_error.backup_path.is_some() {
+                "b: move broken file aside and continue from backup  r: retry"
+            } else if self.items.is_empty() {
+                "b: move broken file aside and start fresh  r: retry"
+            } else {
+                "b: move broken file aside and keep the list shown  r: retry"
+            };
+            let banner = [
+                format!("\x1b[1;31m⚠ Could not load {}\x1b[0m", self.truncate_text(&load_error.path, self.cols.saturating_sub(17))),
+                format!("\x1b[31m{}\x1b[0m", self.truncate_text(&load_error.message, self.cols)),
+                format!("\x1b[33m{}\x1b[0m", self.truncate_text(&source, self.cols)),
+                format!("\x1b[2m{}\x1b[0m", self.truncate_text(action, self.cols)),
+            ];
+            for (row, line) in banner.iter().enumerate() {
+                print!("\x1b[{};1H\x1b[K{}", row + 1, line);
+            }
+        }
+
+        // Header with the active file, list and tag filter sits right above the items
+        let header_parts = self.header_parts();
+        if !header_parts.is_empty() {
+            let header_row = layout.start_row - 1;
+            print!("\x1b[{};1H\x1b[K{}", header_row, text::clip(&header_parts.join("  "), self.cols));
+            if visible.is_empty() {
+                let message = match &self.tag_filter {
+                    Some(tag) => format!("No items tagged #{}", tag),
+                    None => match self.keymap.first_key_label(Context::Normal, Action::Add) {
+                        Some(key) => format!("No items in this list - press '{}' to add one", key),
+                        None => "No items in this list".to_string(),
+                    },
+                };
+                print!("\x1b[{};1H\x1b[K\x1b[2m  {}\x1b[0m", header_row + 1, self.truncate_text(&message, self.cols.saturating_sub(2)));
+            }
+        }
+
+        // The status bar takes the last row, notices and the search query line sit above it
+        let mut bottom_row = self.rows;
+        if self.show_status_bar() {
+            self.render_status_bar(bottom_row);
+            bottom_row = bottom_row.saturating_sub(1);
+        }
+        if self.notice.is_some() {
+            self.render_notice(bottom_row);
+            bottom_row = bottom_row.saturating_sub(1);
+        }
+
+        if self.mode == Mode::Search {
+            let (position, count) = self.search_match_position();
+            let status = if self.search_query.is_empty() {
+                String::new()
+            } else if count == 0 {
+                " \x1b[31m[no matches]\x1b[0m".to_string()
+            } else {
+                format!(" \x1b[2m[{}/{}]\x1b[0m", position, count)
+            };
+            let line = format!("/{}\x1b[7m \x1b[0m{}", self.search_query, status);
+            print!("\x1b[{};1H\x1b[K{}", bottom_row, text::clip(&line, self.cols));
+        }
+
+        // Render visible items
+        let end_row = layout.start_row + layout.rows;
+        let mut row = layout.start_row;
+        for (view_idx, &idx) in visible.iter().enumerate().skip(layout.start_idx) {
+            if row >= end_row {
+                break;
+            }
+            let item = &self.items[idx];
+            let depth = depths[idx];
+            let first_row = row;
+
+            for line in self.item_lines(idx, view_idx, depth) {
+                if row >= end_row {
+                    break;
+                }
+                // Never run past the pane edge, which would wrap into the next row
+                print!("\x1b[{};1H\x1b[K{}", row, text::clip(&line, self.cols));
+                row += 1;
+            }
+
+            if let Some(label) = self.due_label(item, depth) {
+                if self.mode != Mode::Edit || view_idx != self.selected_index {
+                    let label_style = if item.done {
+                        "\x1b[2m"
+                    } else if self.is_overdue(item) {
+                        "\x1b[31m"
+                    } else if item.due == self.today {
+                        "\x1b[33m"
                     } else {
-                        break; // Can't move down anymore
-                    }
-                } else {
-                    break; // Item not found
+                        "\x1b[2m"
+                    };
+                    let column = self.cols.saturating_sub(text::width(&label)) + 1;
+                    print!("\x1b[{};{}H{}{}\x1b[0m", first_row, column, label_style, label);
                 }
             }
-            self.save_todos();
         }
     }
 
-    fn add_new_item(&mut self) {
-        let new_id = self.next_id;
-        self.next_id += 1;
+    fn header_parts(&self) -> Vec<String> {
+        // The active file (when following the focused pane), the active list (when there are
+        // several) and the tag filter
+        let mut header_parts = Vec::new();
+        if self.follow_cwd {
+
+            let hint = if self.project_dir.is_none() && self.git_root.is_some() {
+                self.header_hint(&[(Action::StartProjectFile, "start a project list")])
+            } else {
+                String::new()
+            };
+            header_parts.push(format!("\x1b[35m[{}]\x1b[0m{}", self.todo_file_label(), hint));
+        }
+        if self.lists.len() > 1 {
+            // Both step keys share one hint, as in `[ ]: switch`
+            let switch = match (
+                self.keymap.first_key_label(Context::Normal, Action::PrevList),
+                self.keymap.first_key_label(Context::Normal, Action::NextList),
+            ) {
+                (Some(prev), Some(next)) => format!(", {} {}: switch", prev, next),
+                _ => String::new(),
+            };
+            let lists = match self.keymap.first_key_label(Context::Normal, Action::Lists) {
+                Some(key) => format!(", {}: lists", key),
+                None => String::new(),
+            };
+            header_parts.push(format!(
+                "\x1b[1m{}\x1b[0m \x1b[2m({}/{}{}{})\x1b[0m",
+                self.active_list,
+                self.active_list_position() + 1,
+                self.lists.len(),
+                switch,
+                lists
+            ));
+        }
+        if let Some(tag) = &self.tag_filter {
+            let hint = self.header_hint(&[(Action::NextTag, "next tag"), (Action::ClearTag, "clear")]);
+            header_parts.push(format!("\x1b[36m#{}\x1b[0m{}", tag, hint));
+        }
+        header_parts
+    }
 
-        let (insert_pos, new_display_order) = if self.items.is_empty() {
-            (0, self.next_display_order)
+    fn header_hint(&self, hints: &[(Action, &str)]) -> String {
+        // ` (key: what, ...)` for the bound ones, with the keys taken from the keymap
+        let hints: Vec<String> = hints
+            .iter()
+            .filter_map(|&(action, what)| Some(format!("{}: {}", self.keymap.first_key_label(Context::Normal, action)?, what)))
+            .collect();
+        if hints.is_empty() {
+            String::new()
         } else {
-            // Check if cursor is currently on a completed item
-            let current_item_is_done = self
-                .items
-                .get(self.selected_index)
-                .map(|item| item.done)
-                .unwrap_or(false);
-
-            let insert_pos = if current_item_is_done {
-                // Cursor is on completed item - snap to end of todo section
-                self.items
-                    .iter()
-                    .position(|item| item.done)
-                    .unwrap_or(self.items.len())
+            format!(" \x1b[2m({})\x1b[0m", hints.join(", "))
+        }
+    }
+
+    fn list_layout(&self) -> ListLayout {
+        let visible = self.visible_indices();
+        let depths = self.item_depths();
+
+        // Rows above the items go to the load error banner and the header, rows below them to
+        // the status bar, notices and the search query line
+        let top = if self.load_error.is_some() { LOAD_ERROR_BANNER_ROWS } else { 0 }
+            + usize::from(!self.header_parts().is_empty());
+        let bottom = usize::from(self.show_status_bar())
+            + usize::from(self.notice.is_some())
+            + usize::from(self.mode == Mode::Search);
+        let rows = self.rows.saturating_sub(top + bottom);
+
+        // Items take one or more rows. Start where the list was last scrolled to, moving just
+        // far enough that the selected item fits.
+        let heights: Vec<usize> = visible
+            .iter()
+            .enumerate()
+            .map(|(view_idx, &idx)| self.item_lines(idx, view_idx, depths[idx]).len())
+            .collect();
+        let selected = self.selected_index.min(visible.len().saturating_sub(1));
+        let mut start_idx = self.scroll_top.min(selected);
+        let mut used_rows: usize = heights.get(start_idx..=selected).map(|heights| heights.iter().sum()).unwrap_or(0);
+        while start_idx < selected && used_rows > rows {
+            used_rows -= heights[start_idx];
+            start_idx += 1;
+        }
+
+        ListLayout {
+            visible,
+            depths,
+            start_row: top + 1,
+            rows,
+            start_idx,
+            heights,
+        }
+    }
+
+    fn due_label(&self, item: &TodoItem, depth: usize) -> Option<String> {
+        // Right-aligned due date, relative once we know today's date. Left out when the pane is
+        // too narrow to show it next to any text.
+        item.due
+            .map(|due| match self.today {
+                Some(today) => date::relative_label(due, today),
+                None => due.to_string(),
+            })
+            .filter(|label| text::width(label) + 8 + depth * 2 <= self.cols)
+    }
+
+    fn is_overdue(&self, item: &TodoItem) -> bool {
+        !item.done && matches!((item.due, self.today), (Some(due), Some(today)) if due < today)
+    }
+
+    fn item_lines(&self, idx: usize, view_idx: usize, depth: usize) -> Vec<String> {
+        // The rows an item takes on screen: one, or several when wrapping long text
+        let item = &self.items[idx];
+
+        // Determine styling based on state
+        let (bullet, style_start, style_end) = if item.done {
+            ("✓", "\x1b[2m", "\x1b[0m") // Dimmed with checkmark
+        } else if self.is_overdue(item) {
+            ("•", "\x1b[31m", "\x1b[0m") // Red for overdue items
+        } else {
+            ("•", "", "")
+        };
+
+        // Highlight selected item with underline instead of background
+        let (highlight_start, highlight_end) = if view_idx == self.selected_index {
+            if self.mode == Mode::Edit {
+                ("\x1b[4;36m", "\x1b[0m") // Underlined cyan for edit mode
+            } else {
+                ("\x1b[4m", "\x1b[0m") // Simple underline for selection
+            }
+        } else {
+            ("", "")
+        };
+
+        // Show grab indicator (minimal)
+        let grab_indicator = if self.grabbed_item_id == Some(item.id) {
+            "▶ "
+        } else {
+            "  "
+        };
+
+        // Dimmed indent guides, one per nesting level
+        let indent_guides = if depth > 0 {
+            format!("\x1b[2m{}\x1b[0m", "│ ".repeat(depth))
+        } else {
+            String::new()
+        };
+
+        // Collapsed parents show how many items they hide
+        let hidden_count = if item.collapsed {
+            self.subtree_end(idx) - idx - 1
+        } else {
+            0
+        };
+        let mut trailing_markers = if hidden_count > 0 {
+            format!(" \x1b[2m(+{})\x1b[0m", hidden_count)
+        } else {
+            String::new()
+        };
+
+        // Items with notes are marked, after the collapsed count if there is one
+        if !item.notes.is_empty() {
+            trailing_markers.push_str(" \x1b[2m≡\x1b[0m");
+        }
+
+        // Colored priority dot, restoring the line's styling after it
+        let priority_marker = if item.priority != Priority::None {
+            format!("{}●\x1b[0m{}{} ", item.priority.color(), highlight_start, style_start)
+        } else {
+            String::new()
+        };
+
+        if self.mode == Mode::Edit && view_idx == self.selected_index {
+            // In edit mode: show user input or ghost text if empty, scrolled to keep the
+            // cursor on screen next to the grab indicator, guides, bullet and priority
+            let prefix_width = 4 + depth * 2 + if item.priority != Priority::None { 2 } else { 0 };
+            let editor = self.edit_buffer.render(self.cols.saturating_sub(prefix_width));
+            let display_content = if self.edit_buffer.is_empty() {
+                // Show original text as faded ghost placeholder after the cursor
+                format!("{}\x1b[2m{}\x1b[0m", editor, item.text)
             } else {
-                // Cursor is on todo item - insert above current position
-                self.selected_index
+                // Show user input with the cursor
+                editor
             };
 
-            let new_display_order = if insert_pos < self.items.len() {
-                let target_order = self.items[insert_pos].display_order;
-                // Shift all items at or after insert_pos up by 1
-                for item in self.items.iter_mut().skip(insert_pos) {
-                    item.display_order += 1;
+            return vec![format!("{}{}{}{} {}{}{}{}",
+                grab_indicator,
+                indent_guides,
+                highlight_start,
+                bullet,
+                priority_marker,
+                display_content,
+                style_end,
+                highlight_end
+            )];
+        }
+
+        // Account for grab indicator, indent guides, bullet, priority and collapsed markers
+        let mut marker_width = if hidden_count > 0 { hidden_count.to_string().len() + 4 } else { 0 };
+        if !item.notes.is_empty() {
+            marker_width += 2;
+        }
+        if item.priority != Priority::None {
+            marker_width += 2;
+        }
+        if let Some(label) = self.due_label(item, depth) {
+            marker_width += text::width(&label) + 1;
+        }
+        let max_text_width = self.cols.saturating_sub(6 + depth * 2 + marker_width);
+        let text_lines = if self.wrap {
+            text::wrap(&item.text, max_text_width)
+        } else {
+            vec![self.truncate_text(&item.text, max_text_width)]
+        };
+
+        // Continuation rows hang under the text, past the bullet and priority dot
+        let hanging_indent = if item.priority != Priority::None { "    " } else { "  " };
+        let last_line = text_lines.len() - 1;
+        text_lines
+            .iter()
+            .enumerate()
+            .map(|(line_idx, line_text)| {
+                let line_text = highlight_spans(
+                    line_text,
+                    &self.search_spans(line_text),
+                    &format!("{}{}", highlight_start, style_start),
+                );
+                let marker = if line_idx == last_line { trailing_markers.as_str() } else { "" };
+                if line_idx == 0 {
+                    format!("{}{}{}{}{} {}{}{}{}{}",
+                        grab_indicator,
+                        indent_guides,
+                        highlight_start,
+                        style_start,
+                        bullet,
+                        priority_marker,
+                        line_text,
+                        style_end,
+                        highlight_end,
+                        marker
+                    )
+                } else {
+                    format!("  {}{}{}{}{}{}{}{}",
+                        indent_guides,
+                        hanging_indent,
+                        highlight_start,
+                        style_start,
+                        line_text,
+                        style_end,
+                        highlight_end,
+                        marker
+                    )
                 }
-                target_order
+            })
+            .collect()
+    }
+
+    fn render_list_picker(&self) {
+        let title = match self.moving_item_id {
+            Some(_) => "Move to list",
+            None => "Lists",
+        };
+        print!("\x1b[1;1H\x1b[K\x1b[1m{}\x1b[0m", title);
+
+        // The title takes the first row, the hints or name prompt the last one
+        let available_rows = self.rows.saturating_sub(2);
+        let start_idx = if self.list_picker_index >= available_rows {
+            self.list_picker_index.saturating_sub(available_rows.saturating_sub(1))
+        } else {
+            0
+        };
+        let end_idx = std::cmp::min(start_idx + available_rows, self.lists.len());
+
+        for (display_row, position) in (start_idx..end_idx).enumerate() {
+            let list = &self.lists[position];
+            let count = self.items.iter().filter(|item| item.list == list.name).count();
+            let done = self.items.iter().filter(|item| item.list == list.name && item.done).count();
+            let marker = if list.name == self.active_list { "• " } else { "  " };
+            let (highlight_start, highlight_end) = if position == self.list_picker_index {
+                ("\x1b[4m", "\x1b[0m")
             } else {
-                self.next_display_order
+                ("", "")
             };
+            let name = self.truncate_text(&list.name, self.cols.saturating_sub(20));
+            let line = format!("{}{}{}{} \x1b[2m{}/{} done\x1b[0m", marker, highlight_start, name, highlight_end, done, count);
+            print!("\x1b[{};1H\x1b[K{}", display_row + 2, text::clip(&line, self.cols));
+        }
+
+        if self.mode == Mode::ListName {
+            let prompt = if self.renaming_list.is_some() { "Rename to" } else { "New list" };
+            let prompt = format!("{}: ", prompt);
+            let editor = self.edit_buffer.render(self.cols.saturating_sub(text::width(&prompt)));
+            print!("\x1b[{};1H\x1b[K{}\x1b[0m", self.rows, text::clip(&format!("{}{}", prompt, editor), self.cols));
+        } else if self.notice.is_some() {
+            self.render_notice(self.rows);
+        } else {
+            let pick = if self.moving_item_id.is_some() { "move here" } else { "switch" };
+            let hints = format!("Enter: {}  n: new  r: rename  d: delete  Esc: back", pick);
+            print!("\x1b[{};1H\x1b[K\x1b[2m{}\x1b[0m", self.rows, self.truncate_text(&hints, self.cols));
+        }
+    }
 
-            (insert_pos, new_display_order)
+    fn render_code_comments(&self) {
+        let title = if self.scanning && self.code_comments.is_empty() {
+            "Code TODOs \x1b[0m\x1b[2m· scanning…".to_string()
+        } else if self.code_comments.len() >= scan::MAX_COMMENTS {
+            format!("Code TODOs \x1b[0m\x1b[2m· first {} found", self.code_comments.len())
+        } else {
+            format!("Code TODOs \x1b[0m\x1b[2m· {} found", self.code_comments.len())
         };
+        print!("\x1b[1;1H\x1b[K\x1b[1m{}\x1b[0m", title);
+        if self.code_comments.is_empty() && !self.scanning {
+            print!("\x1b[2;1H\x1b[K\x1b[2m  No TODO, FIXME or HACK comments found\x1b[0m");
+        }
 
-        let new_item = TodoItem {
-            text: String::new(),
-            done: false,
-            id: new_id,
-            display_order: new_display_order,
+        // The title takes the first row, the hints or a notice the last one
+        let available_rows = self.rows.saturating_sub(2);
+        let start_idx = self.code_comment_index.saturating_sub(available_rows.saturating_sub(1));
+        let end_idx = std::cmp::min(start_idx + available_rows, self.code_comments.len());
+
+        for (display_row, position) in (start_idx..end_idx).enumerate() {
+            let comment = &self.code_comments[position];
+            // Comments already added to the list are checked off
+            let marker = if self.is_promoted(comment) { "\x1b[2m✓\x1b[0m " } else { "  " };
+            let (highlight_start, highlight_end) = if position == self.code_comment_index {
+                ("\x1b[4m", "\x1b[0m")
+            } else {
+                ("", "")
+            };
+            let kind_style = match comment.kind {
+                "FIXME" => "\x1b[31m",
+                "HACK" => "\x1b[33m",
+                _ => "\x1b[36m",
+            };
+            let location = comment.location();
+            let text_width = self.cols.saturating_sub(4 + comment.kind.len() + text::width(&location));
+            let line = format!(
+                "{}{}{}{}\x1b[0m{} {}{} \x1b[2m{}\x1b[0m",
+                marker,
+                highlight_start,
+                kind_style,
+                comment.kind,
+                highlight_start,
+                self.truncate_text(&comment.text, text_width),
+                highlight_end,
+                location
+            );
+            print!("\x1b[{};1H\x1b[K{}", display_row + 2, text::clip(&line, self.cols));
+        }
+
+        if self.notice.is_some() {
+            self.render_notice(self.rows);
+        } else {
+            let hints = "Enter: open  p: add to list  r: rescan  Esc: back";
+            print!("\x1b[{};1H\x1b[K\x1b[2m{}\x1b[0m", self.rows, self.truncate_text(hints, self.cols));
+        }
+    }
+
+    fn render_reference_picker(&self) {
+        print!("\x1b[1;1H\x1b[K\x1b[1mOpen reference\x1b[0m");
+
+        // The title takes the first row, the hints the last one
+        let references = self.current_item_references();
+        let available_rows = self.rows.saturating_sub(2);
+        let start_idx = self.reference_index.saturating_sub(available_rows.saturating_sub(1));
+        let end_idx = std::cmp::min(start_idx + available_rows, references.len());
+
+        for (display_row, position) in (start_idx..end_idx).enumerate() {
+            let (highlight_start, highlight_end) = if position == self.reference_index {
+                ("\x1b[4m", "\x1b[0m")
+            } else {
+                ("", "")
+            };
+            let label = self.truncate_text(&references[position].label(), self.cols.saturating_sub(2));
+            print!("\x1b[{};1H\x1b[K  {}\x1b[35m{}\x1b[0m{}", display_row + 2, highlight_start, label, highlight_end);
+        }
+
+        let hints = "Enter: open  Esc: back";
+        print!("\x1b[{};1H\x1b[K\x1b[2m{}\x1b[0m", self.rows, self.truncate_text(hints, self.cols));
+    }
+
+    fn details_lines(&self) -> (Vec<String>, Vec<String>) {
+        // The item's wrapped text and a line of facts about it above, its wrapped notes below
+        let item = match self.selected_item_index() {
+            Some(idx) => &self.items[idx],
+            None => return (Vec::new(), Vec::new()),
         };
 
-        self.items.insert(insert_pos, new_item);
-        self.selected_index = insert_pos;
-        self.next_display_order = self
-            .items
+        let mut header: Vec<String> = text::wrap(&item.text, self.cols)
+            .into_iter()
+            .map(|line| format!("\x1b[1m{}\x1b[0m", line))
+            .collect();
+        let mut facts = Vec::new();
+        if item.done {
+            facts.push("done".to_string());
+        }
+        if item.priority != Priority::None {
+            facts.push(format!("{}{}\x1b[0m\x1b[2m priority", item.priority.color(), item.priority.name()));
+        }
+        if let Some(due) = item.due {
+            facts.push(match self.today {
+                Some(today) => format!("due {}", date::relative_label(due, today)),
+                None => format!("due {}", due),
+            });
+        }
+        if self.lists.len() > 1 {
+            facts.push(format!("in {}", item.list));
+        }
+        if !facts.is_empty() {
+            header.push(format!("\x1b[2m{}\x1b[0m", facts.join(" · ")));
+        }
+
+        let notes = item.notes.lines().flat_map(|line| text::wrap(line, self.cols)).collect();
+        (header, notes)
+    }
+
+    fn render_item_details(&self) {
+        let (header, notes) = self.details_lines();
+        let mut row = 1;
+        for line in &header {
+            print!("\x1b[{};1H\x1b[K{}", row, text::clip(line, self.cols));
+            row += 1;
+        }
+        print!("\x1b[{};1H\x1b[K\x1b[2m{}\x1b[0m", row, "─".repeat(self.cols));
+        row += 1;
+
+        // Notes fill the rows between the separator and the hints on the last row
+        let body_rows = self.rows.saturating_sub(row);
+        let body = if self.mode == Mode::Notes {
+            self.notes_editor.render(self.cols, body_rows)
+        } else if notes.is_empty() {
+            vec!["\x1b[2mNo notes yet - press 'e' to add some\x1b[0m".to_string()]
+        } else {
+            let scroll = self.details_scroll.min(notes.len().saturating_sub(body_rows));
+            notes.into_iter().skip(scroll).take(body_rows).collect()
+        };
+        for line in body.iter().take(body_rows) {
+            print!("\x1b[{};1H\x1b[K{}\x1b[0m", row, text::clip(line, self.cols));
+            row += 1;
+        }
+
+        if self.notice.is_some() {
+            self.render_notice(self.rows);
+        } else {
+            let hints = if self.mode == Mode::Notes {
+                "Enter: new line  Ctrl+V: paste  Esc: done"
+            } else {
+                "e: edit notes  j/k: scroll  Esc: back"
+            };
+            print!("\x1b[{};1H\x1b[K\x1b[2m{}\x1b[0m", self.rows, self.truncate_text(hints, self.cols));
+        }
+    }
+
+    fn help_lines(&self) -> Vec<String> {
+        // Generated from the keymap the key handlers use, so it always matches what keys do
+        let context = self.key_context();
+        let mut rows: Vec<(String, &str)> = self
+            .keymap
+            .bindings(context)
+            .filter(|binding| !binding.keys.is_empty())
+            .filter(|binding| binding.action != Action::StartProjectFile || self.follow_cwd)
+            .map(|binding| {
+                let keys: Vec<String> = binding.keys.iter().map(keymap::key_label).collect();
+                (keys.join(" / "), binding.description)
+            })
+            .collect();
+
+        // Text fields handle the remaining keys themselves
+        let mut notes = Vec::new();
+        let editing: Vec<(&str, &str)> = match context {
+            Context::Edit | Context::ListName => line_editor::KEYS.to_vec(),
+            Context::Notes => [notes_editor::KEYS, line_editor::KEYS].concat(),
+            Context::Grab => {
+                notes.push("Other keys work as in Normal mode");
+                Vec::new()
+            }
+            _ => Vec::new(),
+        };
+        let editing_start = rows.len();
+        rows.extend(editing.into_iter().map(|(keys, description)| (keys.to_string(), description)));
+
+        let key_width = rows
             .iter()
-            .map(|item| item.display_order)
+            .map(|(keys, _)| text::width(keys))
             .max()
             .unwrap_or(0)
-            + 1;
+            .min(self.cols / 2);
+        let mut lines = Vec::new();
+        for (position, (keys, description)) in rows.iter().enumerate() {
+            if position == editing_start {
+                lines.push(String::new());
+                lines.push("\x1b[1mEditing\x1b[0m".to_string());
+            }
+            let keys = self.truncate_text(keys, key_width);
+            let padding = " ".repeat(key_width - text::width(&keys));
+            lines.push(format!("  \x1b[36m{}\x1b[0m{}  {}", keys, padding, description));
+        }
+        for note in notes {
+            lines.push(String::new());
+            lines.push(format!("\x1b[2m{}\x1b[0m", note));
+        }
 
-        self.start_editing_current();
+        // Problems with bind_ options stay findable after their notice is gone
+        if !self.keymap_problems.is_empty() {
+            lines.push(String::new());
+            lines.push("\x1b[1;33mKey binding problems\x1b[0m".to_string());
+            for problem in &self.keymap_problems {
+                lines.push(format!("  \x1b[33m{}\x1b[0m", problem));
+            }
+        }
+        lines
     }
 
-    fn start_editing_current(&mut self) {
-        if let Some(item) = self.items.get(self.selected_index) {
-            self.edit_buffer = item.text.clone();
-            self.mode = Mode::Edit;
+    fn render_help(&self) {
+        let title = format!("Keys: {}", self.key_context().title());
+        print!("\x1b[1;1H\x1b[K\x1b[1m{}\x1b[0m", self.truncate_text(&title, self.cols));
+
+        // The title takes the first row, the hints the last one
+        let lines = self.help_lines();
+        let body_rows = self.rows.saturating_sub(2);
+        let scroll = self.help_scroll.min(lines.len().saturating_sub(body_rows));
+        for (display_row, line) in lines.iter().skip(scroll).take(body_rows).enumerate() {
+            print!("\x1b[{};1H\x1b[K{}", display_row + 2, text::clip(line, self.cols));
         }
+
+        let close = self
+            .keymap
+            .bindings(Context::Help)
+            .find(|binding| binding.action == Action::Back)
+            .and_then(|binding| binding.keys.first())
+            .map(keymap::key_label)
+            .unwrap_or_default();
+        let hints = if lines.len() > body_rows {
+            format!("↑/↓: scroll ({}-{} of {})  {}: close", scroll + 1, (scroll + body_rows).min(lines.len()), lines.len(), close)
+        } else {
+            format!("{}: close", close)
+        };
+        print!("\x1b[{};1H\x1b[K\x1b[2m{}\x1b[0m", self.rows, self.truncate_text(&hints, self.cols));
     }
 
-    fn save_edit(&mut self) {
-        if let Some(item) = self.items.get_mut(self.selected_index) {
-            item.text = self.edit_buffer.trim().to_string();
-            if item.text.is_empty() {
-                // Remove empty items
-                self.items.remove(self.selected_index);
-                if self.selected_index >= self.items.len() && !self.items.is_empty() {
-                    self.selected_index = self.items.len() - 1;
-                }
-            }
-            self.save_todos();
-        }
-        self.mode = Mode::Normal;
-        self.edit_buffer.clear();
+    fn truncate_text(&self, text: &str, max_width: usize) -> String {
+        // Measured in terminal columns, so wide and combining characters line up
+        text::truncate(text, max_width)
     }
 
-    fn cancel_edit(&mut self) {
-        // If this was a new empty item, remove it
-        if let Some(item) = self.items.get(self.selected_index) {
-            if item.text.is_empty() {
-                self.items.remove(self.selected_index);
-                if self.selected_index >= self.items.len() && !self.items.is_empty() {
-                    self.selected_index = self.items.len() - 1;
+
+    fn todos_path(&self) -> String {
+        format!("{}/{}", self.cwd, self.filename)
+    }
+
+    fn load_todos(&mut self) {
+        // Load todos from filesystem for persistence
+        let todos_path = self.todos_path();
+        let error = match std::fs::read_to_string(&todos_path) {
+            Ok(data) => match schema::parse(&data) {
+                Ok(file) => {
+                    self.disk_hash = content_hash(&data);
+                    self.disk_base = file.clone();
+                    self.apply_loaded_file(file);
+                    self.load_history();
+                    return;
                 }
+                Err(error) => error,
+            },
+            // No file yet - start with an empty list
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return,
+            Err(error) => error.to_string(),
+        };
+
+        // The main file is unreadable - go read-only so nothing overwrites it, showing the
+        // newest backup that still parses if there is one
+        let mut load_error = LoadError {
+            path: todos_path.clone(),
+            message: error,
+            backup_path: None,
+        };
+        for generation in 1..=self.backup_count {
+            let backup_path = storage::backup_path(&todos_path, generation);
+            let file = std::fs::read_to_string(&backup_path)
+                .ok()
+                .and_then(|data| schema::parse(&data).ok());
+            if let Some(file) = file {
+                self.apply_loaded_file(file);
+                load_error.backup_path = Some(backup_path);
+                break;
             }
-            // If editing existing item, revert any changes by doing nothing
-            // The original text remains unchanged
         }
-        self.mode = Mode::Normal;
-        self.edit_buffer.clear();
+        self.load_error = Some(load_error);
     }
 
-    fn delete_current_item(&mut self) {
-        if !self.items.is_empty() {
-            self.items.remove(self.selected_index);
-            if self.selected_index >= self.items.len() && !self.items.is_empty() {
-                self.selected_index = self.items.len() - 1;
-            }
-            self.save_todos();
+    fn retry_load(&mut self) {
+        self.items.clear();
+        self.history = History::default();
+        self.next_id = 0;
+        self.next_display_order = 0;
+        self.lists.clear();
+        self.file_extra.clear();
+        self.disk_base = TodoFile::default();
+        self.disk_hash = 0;
+        self.selected_index = 0;
+        self.load_error = None;
+        self.load_todos();
+        self.normalize_lists();
+        if self.load_error.is_none() {
+            self.notice = Some(Notice::Info(format!("Loaded {}", self.todos_path())));
         }
     }
 
-    fn sort_items(&mut self) {
-        // Simple sort: todo items first (by display_order), then done items (by display_order)
-        let current_id = self.get_current_item_id();
+    fn set_aside_broken_file(&mut self) {
+        // Move the unreadable file out of the way and carry on with the backup (or an empty list)
+        let load_error = match self.load_error.take() {
+            Some(load_error) => load_error,
+            None => return,
+        };
 
-        self.items.sort_by(|a, b| {
-            match (a.done, b.done) {
-                (false, true) => std::cmp::Ordering::Less,  // Todo items come first
-                (true, false) => std::cmp::Ordering::Greater, // Done items come last
-                _ => a.display_order.cmp(&b.display_order),   // Within same status, sort by original order
-            }
-        });
+        let broken_path = (1..)
+            .map(|n| if n == 1 { format!("{}.broken", load_error.path) } else { format!("{}.broken.{}", load_error.path, n) })
+            .find(|path| !std::path::Path::new(path).exists())
+            .unwrap_or_else(|| format!("{}.broken", load_error.path));
 
-        // Update selected index to follow the moved item
-        if let Some(new_pos) = self.items.iter().position(|item| item.id == current_id) {
-            self.selected_index = new_pos;
+        match std::fs::rename(&load_error.path, &broken_path) {
+            Ok(()) => {
+                self.notice = Some(Notice::Info(format!("Moved broken file to {}", broken_path)));
+                self.save_todos();
+            }
+            Err(error) => {
+                self.notice = Some(Notice::Error(format!("Could not move {}: {}", load_error.path, error)));
+                self.load_error = Some(load_error);
+            }
         }
     }
 
+    fn apply_loaded_file(&mut self, file: TodoFile) {
+        self.items = file.items;
+        for item in &mut self.items {
+            // Tags are derived from the text, so files written before tags existed still work
+            item.tags = parse_tags(&item.text);
+        }
 
-    fn render_empty_state(&self) {
-        let message = "Press 'a' to add a todo";
-        let y = self.rows / 2;
-        let x = (self.cols.saturating_sub(message.len())) / 2;
+        // Never hand out an id or order that is already taken, even if the counters are stale
+        let max_id = self.items.iter().map(|item| item.id + 1).max().unwrap_or(0);
+        let max_display_order = self.items.iter().map(|item| item.display_order + 1).max().unwrap_or(0);
+        self.next_id = file.next_id.max(max_id);
+        self.next_display_order = file.next_display_order.max(max_display_order);
 
-        print!("\x1b[{};{}H\x1b[2m{}\x1b[0m", y + 1, x + 1, message);
+        self.lists = file.lists;
+        self.file_extra = file.extra;
+        self.normalize_lists();
+        self.sort_items();
     }
 
-    fn render_todo_list(&self) {
-        let start_row = 1;
-        let available_rows = self.rows;
-
-        // Calculate visible range
-        let start_idx = if self.selected_index >= available_rows {
-            self.selected_index.saturating_sub(available_rows.saturating_sub(1))
-        } else {
-            0
-        };
-        let end_idx = std::cmp::min(start_idx + available_rows, self.items.len());
-
-        // Render visible items
-        for (display_row, idx) in (start_idx..end_idx).enumerate() {
-            let item = &self.items[idx];
-            let row = start_row + display_row;
-
-            // Move cursor to start of line
-            print!("\x1b[{};1H", row);
+    fn to_file(&self) -> TodoFile {
+        TodoFile {
+            version: schema::SCHEMA_VERSION,
+            next_id: self.next_id,
+            next_display_order: self.next_display_order,
+            lists: self.lists.clone(),
+            items: self.items.clone(),
+            extra: self.file_extra.clone(),
+        }
+    }
 
-            // Clear line
-            print!("\x1b[K");
+    fn save_todos(&mut self) {
+        // Never overwrite a file we failed to load
+        if self.load_error.is_some() {
+            return;
+        }
 
-            // Determine styling based on state
-            let (bullet, style_start, style_end) = if item.done {
-                ("✓", "\x1b[2m", "\x1b[0m") // Dimmed with checkmark
-            } else {
-                ("•", "", "")
-            };
+        // Pick up changes made by other instances first, so the last save doesn't wipe them
+        self.merge_external_changes();
+        if self.load_error.is_some() {
+            return;
+        }
 
-            // Highlight selected item with underline instead of background
-            let (highlight_start, highlight_end) = if idx == self.selected_index {
-                if self.mode == Mode::Edit {
-                    ("\x1b[4;36m", "\x1b[0m") // Underlined cyan for edit mode
-                } else {
-                    ("\x1b[4m", "\x1b[0m") // Simple underline for selection
+        // Save todos to filesystem for persistence, keeping backups of earlier versions
+        let todos_path = self.todos_path();
+        let file = self.to_file();
+        // A failed backup is reported, but doesn't keep the list itself from being saved
+        let mut backup_error = None;
+        let result = serde_json::to_string_pretty(&file)
+            .map_err(|error| error.to_string())
+            .and_then(|data| {
+                if self.backup_due {
+                    self.backup_due = false;
+                    backup_error = storage::rotate_backups(&todos_path, self.backup_count).err();
                 }
-            } else {
-                ("", "")
-            };
+                storage::write_atomic(&todos_path, &data).map_err(|error| error.to_string())?;
+                Ok(data)
+            });
+
+        match result {
+            Ok(data) => {
+                self.disk_hash = content_hash(&data);
+                self.disk_base = file;
+                self.notice = match backup_error {
+                    Some(error) => Some(Notice::Error(format!("Could not back up {}: {}", todos_path, error))),
+                    None if matches!(self.notice, Some(Notice::Error(_))) => None,
+                    None => self.notice.take(),
+                };
+            }
+            Err(error) => {
+                self.notice = Some(Notice::Error(format!("Could not save {}: {}", todos_path, error)));
+            }
+        }
+        self.save_history();
+    }
 
-            // Show grab indicator (minimal)
-            let grab_indicator = if self.grabbed_item_id == Some(item.id) {
-                "▶ "
-            } else {
-                "  "
-            };
+    fn merge_external_changes(&mut self) -> bool {
+        // Another instance (or an editor) may have written the file since we last read or saved it
+        if self.load_error.is_some() {
+            return false;
+        }
+        let todos_path = self.todos_path();
+        let data = match std::fs::read_to_string(&todos_path) {
+            Ok(data) => data,
+            Err(_) => return false,
+        };
+        let hash = content_hash(&data);
+        if hash == self.disk_hash {
+            return false;
+        }
 
-            // Render the item
-            let display_text = if self.mode == Mode::Edit && idx == self.selected_index {
-                // In edit mode: show user input or ghost text if empty
-                let display_content = if self.edit_buffer.is_empty() {
-                    // Show original text as faded ghost placeholder
-                    format!("\x1b[2m{}\x1b[0m", item.text)
-                } else {
-                    // Show user input normally
-                    self.edit_buffer.clone()
-                };
-                
-                format!("{}{}{} {}{}{}",
-                    grab_indicator,
-                    highlight_start,
-                    bullet,
-                    display_content,
-                    style_end,
-                    highlight_end
-                )
-            } else {
-                let max_text_width = self.cols.saturating_sub(6); // Account for grab indicator and bullet
-                let truncated_text = self.truncate_text(&item.text, max_text_width);
-
-                format!("{}{}{}{} {}{}{}",
-                    grab_indicator,
-                    highlight_start,
-                    style_start,
-                    bullet,
-                    truncated_text,
-                    style_end,
-                    highlight_end
-                )
-            };
+        let theirs = match schema::parse(&data) {
+            Ok(file) => file,
+            Err(error) => {
+                // Don't write over a hand edit that went wrong - go read-only like a failed load
+                self.load_error = Some(LoadError {
+                    path: todos_path,
+                    message: error,
+                    backup_path: None,
+                });
+                self.mode = Mode::Normal;
+                self.edit_buffer.clear();
+                return true;
+            }
+        };
 
-            print!("{}", display_text);
+        let current_id = self.selected_item_index().map(|idx| self.items[idx].id);
+        let merged = merge::merge(&self.disk_base.items, &self.items, &theirs.items, self.next_id);
+        let renumber = |id: usize| merged.renumbered.get(&id).copied().unwrap_or(id);
+        let changed = merged.items != self.items || (self.lists == self.disk_base.lists && self.lists != theirs.lists);
+
+        self.next_id = [self.next_id, theirs.next_id, merged.items.iter().map(|item| item.id + 1).max().unwrap_or(0)]
+            .into_iter()
+            .max()
+            .unwrap_or(0);
+        self.next_display_order = self.next_display_order.max(theirs.next_display_order);
+        self.grabbed_item_id = self.grabbed_item_id.map(renumber);
+        let current_id = current_id.map(renumber);
+        // What our undo steps were taken against, in the merged ids
+        let mut before = self.snapshot();
+        for item in &mut before.items {
+            item.id = renumber(item.id);
+            item.parent = item.parent.map(renumber);
         }
-    }
+        self.items = merged.items;
 
-    fn truncate_text(&self, text: &str, max_chars: usize) -> String {
-        if max_chars == 0 {
-            return String::new();
+        // List settings and unknown fields follow the disk unless we changed them ourselves
+        if self.lists == self.disk_base.lists {
+            self.lists = theirs.lists.clone();
+        }
+        if self.file_extra == self.disk_base.extra {
+            self.file_extra = theirs.extra.clone();
         }
+        self.disk_hash = hash;
+        self.disk_base = theirs;
 
-        let chars: Vec<char> = text.chars().collect();
-        if chars.len() <= max_chars {
-            return text.to_string();
+        if !changed {
+            return false;
         }
 
-        if max_chars == 1 {
-            return "…".to_string();
+        self.normalize_lists();
+        self.rebase_history(&before, &merged.renumbered);
+        self.sort_items();
+        if let Some(id) = current_id {
+            self.select_item_by_id(id);
         }
+        self.clamp_selection();
+
+        self.notice = Some(Notice::Info(match merged.conflicts {
+            0 => "Reloaded changes made elsewhere".to_string(),
+            1 => "Reloaded changes made elsewhere; 1 item was edited on both sides, kept yours".to_string(),
+            conflicts => format!("Reloaded changes made elsewhere; {} items were edited on both sides, kept yours", conflicts),
+        }));
+        true
+    }
 
-        let mut truncated = String::with_capacity(max_chars);
-        for c in chars.iter().take(max_chars - 1) {
-            truncated.push(*c);
+    fn rebase_history(&mut self, before: &Snapshot, renumbered: &HashMap<usize, usize>) {
+        // Replay each undo step on top of the merged items. Undoing past someone else's change
+        // would silently revert it, so a step that touches one is dropped with all older steps.
+        for stack in [&mut self.history.undo, &mut self.history.redo] {
+            let steps = std::mem::take(stack);
+            let mut rebased: Vec<Snapshot> = steps
+                .into_iter()
+                .rev()
+                .map_while(|step| rebase_snapshot(step, before, &self.items, &self.lists, renumbered))
+                .collect();
+            rebased.reverse();
+            *stack = rebased;
+        }
+        self.pending_edit = self
+            .pending_edit
+            .take()
+            .and_then(|step| rebase_snapshot(step, before, &self.items, &self.lists, renumbered));
+
+        for step in self.history.undo.iter_mut().chain(self.history.redo.iter_mut()).chain(self.pending_edit.as_mut()) {
+            // Ids and orders handed out elsewhere must stay taken after an undo
+            step.next_id = step.next_id.max(self.next_id);
+            step.next_display_order = step.next_display_order.max(self.next_display_order);
         }
-        truncated.push('…');
-        truncated
     }
 
+    fn history_path(&self) -> String {
+        format!("{}/{}.history", self.cwd, self.filename)
+    }
 
-    fn load_todos(&mut self) {
-        // Load todos from filesystem for persistence
-        let todos_path = format!("{}/{}", self.cwd, self.filename);
-        if let Ok(data) = std::fs::read_to_string(&todos_path) {
-            if let Ok(mut loaded_items) = serde_json::from_str::<Vec<TodoItem>>(&data) {
-                // Simple migration: assign display_order based on current position for items that don't have it
-                for (index, item) in loaded_items.iter_mut().enumerate() {
-                    if item.display_order == 0 && index > 0 {
-                        item.display_order = index;
-                    }
+    fn load_history(&mut self) {
+        // Undo history lives next to the todo file so it survives plugin reloads
+        if let Ok(data) = std::fs::read_to_string(self.history_path()) {
+            if let Ok(history) = serde_json::from_str::<History>(&data) {
+                // Ignore history written against a different version of the list
+                if history.fingerprint == items_fingerprint(&self.items) {
+                    self.history = history;
                 }
-
-                self.items = loaded_items;
-                self.next_id = self.items.iter().map(|item| item.id).max().unwrap_or(0) + 1;
-                self.next_display_order = self.items.iter().map(|item| item.display_order).max().unwrap_or(0) + 1;
-                self.sort_items();
             }
         }
     }
 
-    fn save_todos(&self) {
-        // Save todos to filesystem for persistence
-        if let Ok(data) = serde_json::to_string_pretty(&self.items) {
-            let todos_path = format!("{}/{}", self.cwd, self.filename);
-            let _ = std::fs::write(&todos_path, data);
+    fn save_history(&self) {
+        // Only the most recent steps of either kind, which sit at the end of each stack
+        let recent = |steps: &[Snapshot]| steps[steps.len().saturating_sub(SAVED_UNDO_LIMIT)..].to_vec();
+        let history = History {
+            undo: recent(&self.history.undo),
+            redo: recent(&self.history.redo),
+            fingerprint: items_fingerprint(&self.items),
+        };
+        if let Ok(data) = serde_json::to_string(&history) {
+            let _ = storage::write_atomic(&self.history_path(), &data);
         }
     }
 
@@ -663,10 +3460,35 @@ impl State {
 
         match self.mode {
             Mode::Edit => {
-                // In edit mode, append the pasted text to the edit buffer
-                // If multi-line, only take the first line
+                // In edit mode, insert the first line at the cursor
+                // Any further lines are kept as the item's notes
+                let text = text.replace("\r\n", "\n");
+                let (first_line, rest) = text.trim().split_once('\n').unwrap_or((text.trim(), ""));
+                self.edit_buffer.insert(first_line.trim());
+                if !rest.trim().is_empty() {
+                    if !self.pasted_notes.is_empty() {
+                        self.pasted_notes.push('\n');
+                    }
+                    self.pasted_notes.push_str(rest.trim_end());
+                }
+                true
+            }
+            Mode::Notes => {
+                self.notes_editor.insert(&text);
+                true
+            }
+            Mode::Details | Mode::References | Mode::CodeComments => false,
+            Mode::ListName => {
+                let first_line = text.lines().next().unwrap_or("").trim();
+                self.edit_buffer.insert(first_line);
+                true
+            }
+            Mode::Lists => false,
+            Mode::Search => {
+                // In search mode, extend the query with the first line
                 let first_line = text.lines().next().unwrap_or("").trim();
-                self.edit_buffer.push_str(first_line);
+                self.search_query.push_str(first_line);
+                self.update_search();
                 true
             }
             Mode::Normal => {
@@ -689,8 +3511,10 @@ impl State {
         }
     }
 
-    fn parse_markdown_list(&self, text: &str) -> Vec<(String, bool)> {
+    fn parse_markdown_list(&self, text: &str) -> Vec<ParsedItem> {
         let mut items = Vec::new();
+        // Indentation of the enclosing list items, used to turn indentation into nesting depth
+        let mut indent_stack: Vec<usize> = Vec::new();
 
         for line in text.lines() {
             let trimmed = line.trim();
@@ -698,135 +3522,562 @@ impl State {
                 continue;
             }
 
-            // Check for unordered list markers: -, *, +
-            if let Some(content) = trimmed.strip_prefix("- ") {
-                // Check for task list checkbox
-                if let Some(rest) = content.strip_prefix("[ ] ") {
-                    items.push((rest.to_string(), false));
-                } else if let Some(rest) = content.strip_prefix("[x] ") {
-                    items.push((rest.to_string(), true));
-                } else if let Some(rest) = content.strip_prefix("[X] ") {
-                    items.push((rest.to_string(), true));
-                } else {
-                    items.push((content.to_string(), false));
-                }
-            } else if let Some(content) = trimmed.strip_prefix("* ") {
-                // Check for task list checkbox
-                if let Some(rest) = content.strip_prefix("[ ] ") {
-                    items.push((rest.to_string(), false));
-                } else if let Some(rest) = content.strip_prefix("[x] ") {
-                    items.push((rest.to_string(), true));
-                } else if let Some(rest) = content.strip_prefix("[X] ") {
-                    items.push((rest.to_string(), true));
-                } else {
-                    items.push((content.to_string(), false));
-                }
-            } else if let Some(content) = trimmed.strip_prefix("+ ") {
-                // Check for task list checkbox
-                if let Some(rest) = content.strip_prefix("[ ] ") {
-                    items.push((rest.to_string(), false));
-                } else if let Some(rest) = content.strip_prefix("[x] ") {
-                    items.push((rest.to_string(), true));
-                } else if let Some(rest) = content.strip_prefix("[X] ") {
-                    items.push((rest.to_string(), true));
-                } else {
-                    items.push((content.to_string(), false));
-                }
-            } else {
-                // Check for ordered list (1., 2., etc.)
-                let parts: Vec<&str> = trimmed.splitn(2, ". ").collect();
-                if parts.len() == 2 && parts[0].chars().all(|c| c.is_ascii_digit()) && !parts[0].is_empty() {
-                    let content = parts[1];
-                    // Check for task list checkbox in ordered lists too
-                    if let Some(rest) = content.strip_prefix("[ ] ") {
-                        items.push((rest.to_string(), false));
-                    } else if let Some(rest) = content.strip_prefix("[x] ") {
-                        items.push((rest.to_string(), true));
-                    } else if let Some(rest) = content.strip_prefix("[X] ") {
-                        items.push((rest.to_string(), true));
-                    } else {
-                        items.push((content.to_string(), false));
-                    }
-                }
+            let content = match Self::strip_list_marker(trimmed) {
+                Some(content) => content,
+                None => continue,
+            };
+
+            // Tabs count as four spaces of indentation
+            let indent: usize = line
+                .chars()
+                .take_while(|c| c.is_whitespace())
+                .map(|c| if c == '\t' { 4 } else { 1 })
+                .sum();
+            while indent_stack.last().is_some_and(|&parent_indent| parent_indent >= indent) {
+                indent_stack.pop();
             }
+            let depth = indent_stack.len();
+            indent_stack.push(indent);
+
+            // Check for task list checkbox
+            let (text, done) = if let Some(rest) = content.strip_prefix("[ ] ") {
+                (rest, false)
+            } else if let Some(rest) = content.strip_prefix("[x] ") {
+                (rest, true)
+            } else if let Some(rest) = content.strip_prefix("[X] ") {
+                (rest, true)
+            } else {
+                (content, false)
+            };
+
+            let (text, priority) = Self::extract_priority(text);
+
+            items.push(ParsedItem {
+                text,
+                done,
+                depth,
+                priority,
+            });
         }
 
         items
     }
 
-    fn add_multiple_items(&mut self, items: Vec<(String, bool)>) {
-        // Determine insert position
-        let insert_pos = if self.items.is_empty() {
-            0
+    fn strip_list_marker(line: &str) -> Option<&str> {
+        // Check for unordered list markers: -, *, +
+        for marker in ["- ", "* ", "+ "] {
+            if let Some(content) = line.strip_prefix(marker) {
+                return Some(content);
+            }
+        }
+
+        // Check for ordered list (1., 2., etc.)
+        let parts: Vec<&str> = line.splitn(2, ". ").collect();
+        if parts.len() == 2 && parts[0].chars().all(|c| c.is_ascii_digit()) && !parts[0].is_empty() {
+            Some(parts[1])
         } else {
-            let current_item_is_done = self.items.get(self.selected_index)
-                .map(|item| item.done)
-                .unwrap_or(false);
+            None
+        }
+    }
 
-            if current_item_is_done {
-                // Insert at the end of the todo section
-                self.items.iter().position(|item| item.done).unwrap_or(self.items.len())
-            } else {
-                // Insert at current position
-                self.selected_index
-            }
+    fn extract_priority(text: &str) -> (String, Priority) {
+        // Recognize a todo.txt style "(A)" prefix or a "!!" word at the start or end of the text
+        let marker_priority = |word: &str| match word {
+            "(A)" => Some(Priority::Urgent),
+            "(B)" => Some(Priority::High),
+            "(C)" => Some(Priority::Medium),
+            "(D)" => Some(Priority::Low),
+            _ if !word.is_empty() && word.chars().all(|c| c == '!') => match word.len() {
+                1 => Some(Priority::Low),
+                2 => Some(Priority::Medium),
+                3 => Some(Priority::High),
+                _ => Some(Priority::Urgent),
+            },
+            _ => None,
         };
 
+        let text = text.trim();
+        if let Some((first, rest)) = text.split_once(char::is_whitespace) {
+            if let Some(priority) = marker_priority(first) {
+                return (rest.trim_start().to_string(), priority);
+            }
+        }
+        if let Some((rest, last)) = text.rsplit_once(char::is_whitespace) {
+            if let Some(priority) = marker_priority(last) {
+                return (rest.trim_end().to_string(), priority);
+            }
+        }
+
+        (text.to_string(), Priority::None)
+    }
+
+    fn add_multiple_items(&mut self, items: Vec<ParsedItem>) {
+        let before = self.snapshot();
+
+        // Top-level pasted items join the current item's sibling group
+        let base_parent = self.selected_item_index().and_then(|idx| self.items[idx].parent);
+
+        // Ids of the most recently added item at each depth
+        let mut parent_stack: Vec<usize> = Vec::new();
+        let mut first_added_id = None;
+
         // Add all items
-        let mut added_count = 0;
-        for (text, done) in items {
-            if !text.is_empty() {
+        for parsed in items {
+            if !parsed.text.is_empty() {
+                parent_stack.truncate(parsed.depth);
+                let parent = parent_stack.last().copied().or(base_parent);
+                let text = self.with_filter_tag(parsed.text, parent);
                 let new_item = TodoItem {
+                    tags: parse_tags(&text),
                     text,
-                    done,
+                    done: parsed.done,
                     id: self.next_id,
                     display_order: self.next_display_order,
+                    list: self.active_list.clone(),
+                    parent,
+                    priority: parsed.priority,
+                    ..Default::default()
                 };
                 self.next_id += 1;
                 self.next_display_order += 1;
 
-                self.items.insert(insert_pos + added_count, new_item);
-                added_count += 1;
+                parent_stack.push(new_item.id);
+                first_added_id.get_or_insert(new_item.id);
+                self.items.push(new_item);
             }
         }
 
         // Update selection to first newly added item
-        if added_count > 0 {
-            self.selected_index = insert_pos;
+        if let Some(first_added_id) = first_added_id {
             self.sort_items();
+            self.select_item_by_id(first_added_id);
+            self.record_change(before);
             self.save_todos();
         }
     }
 
     fn add_single_item_from_text(&mut self, text: String) {
+        let before = self.snapshot();
+        let parent = self.selected_item_index().and_then(|idx| self.items[idx].parent);
+        let text = self.with_filter_tag(text, parent);
         let new_item = TodoItem {
+            tags: parse_tags(&text),
             text,
             done: false,
             id: self.next_id,
             display_order: self.next_display_order,
+            list: self.active_list.clone(),
+            parent,
+            ..Default::default()
         };
         self.next_id += 1;
         self.next_display_order += 1;
 
-        if self.items.is_empty() {
-            self.items.push(new_item);
-            self.selected_index = 0;
-        } else {
-            let current_item_is_done = self.items.get(self.selected_index)
-                .map(|item| item.done)
-                .unwrap_or(false);
+        let new_id = new_item.id;
+        self.items.push(new_item);
+        self.sort_items();
+        self.select_item_by_id(new_id);
+        self.record_change(before);
+        self.save_todos();
+    }
 
-            let insert_pos = if current_item_is_done {
-                self.items.iter().position(|item| item.done).unwrap_or(self.items.len())
-            } else {
-                self.selected_index
-            };
+    fn handle_message(&mut self, name: &str, payload: &str) -> bool {
+        match message::parse(name, payload) {
+            Ok(Some(Message::Toggle)) => {
+                hide_self();
+                false
+            }
+            Ok(Some(Message::Paste(text))) if self.load_error.is_none() => self.handle_paste(text),
+            Ok(Some(Message::Add(text))) if self.load_error.is_none() => {
+                self.add_item_at_end(&text);
+                true
+            }
+            // The list is read-only until the broken file is dealt with
+            Ok(Some(_)) | Ok(None) => false,
+            Err(error) => {
+                self.notice = Some(Notice::Info(format!("Ignored message {}", error)));
+                true
+            }
+        }
+    }
+
+    fn run_pipe_command(&mut self, command: pipe::Command) -> Result<String, String> {
+        // Scripts can read a list shown from a backup, but nothing may change it
+        if let (Some(load_error), false) = (&self.load_error, matches!(command, pipe::Command::List { .. } | pipe::Command::Help)) {
+            return Err(format!("{} could not be loaded, open the plugin to resolve it", load_error.path));
+        }
+
+        match command {
+            pipe::Command::Add(text) => Ok(format!("Added item {}", self.add_item_at_end(&text))),
+            pipe::Command::Done(ids) => self.mark_done_from_pipe(&ids),
+            pipe::Command::List { json, all } => self.list_for_pipe(json, all),
+            pipe::Command::ClearDone => Ok(self.clear_done_items()),
+            pipe::Command::Help => Ok(pipe::USAGE.to_string()),
+        }
+    }
+
+    fn add_item_at_end(&mut self, text: &str) -> usize {
+        // A top-level item at the end of the active list; further lines become its notes
+        let before = self.snapshot();
+        let text = text.replace("\r\n", "\n");
+        let (first_line, notes) = text.trim().split_once('\n').unwrap_or((text.trim(), ""));
+        let (text, due) = self.extract_due(first_line);
+        let new_item = TodoItem {
+            tags: parse_tags(&text),
+            text,
+            id: self.next_id,
+            display_order: self.next_display_order,
+            list: self.active_list.clone(),
+            due,
+            notes: notes.trim_end().to_string(),
+            ..Default::default()
+        };
+        self.next_id += 1;
+        self.next_display_order += 1;
+
+        let new_id = new_item.id;
+        self.items.push(new_item);
+        self.sort_items();
+        self.record_change(before);
+        self.save_todos();
+        new_id
+    }
+
+    fn mark_done_from_pipe(&mut self, ids: &[usize]) -> Result<String, String> {
+        // All or nothing, so a typo doesn't leave half of the items marked
+        let missing: Vec<String> = ids
+            .iter()
+            .filter(|&&id| !self.items.iter().any(|item| item.id == id))
+            .map(|id| id.to_string())
+            .collect();
+        if !missing.is_empty() {
+            return Err(format!("no item with id {}", missing.join(", ")));
+        }
+
+        Ok(match self.mark_items_done(ids) {
+            1 => "Marked 1 item done".to_string(),
+            count => format!("Marked {} items done", count),
+        })
+    }
 
-            self.items.insert(insert_pos, new_item);
-            self.selected_index = insert_pos;
+    fn mark_items_done(&mut self, ids: &[usize]) -> usize {
+        let before = self.snapshot();
+        let original_cursor_position = self.selected_index;
+        let mut marked = 0;
+        for item in self.items.iter_mut().filter(|item| ids.contains(&item.id)) {
+            item.done = true;
+            marked += 1;
         }
 
+        // Keep the cursor where it was, like toggling does
         self.sort_items();
+        self.selected_index = original_cursor_position.min(self.visible_indices().len().saturating_sub(1));
+        self.record_change(before);
         self.save_todos();
+        marked
+    }
+
+    fn list_for_pipe(&self, json: bool, all: bool) -> Result<String, String> {
+        let listed: Vec<usize> = (0..self.items.len()).filter(|&idx| all || self.items[idx].list == self.active_list).collect();
+        if json {
+            let items: Vec<&TodoItem> = listed.iter().map(|&idx| &self.items[idx]).collect();
+            return serde_json::to_string_pretty(&items).map_err(|error| error.to_string());
+        }
+
+        // One line per item in list order: id, checkbox, indented text, priority and due date
+        let depths = self.item_depths();
+        let mut lines = Vec::new();
+        let mut current_list = None;
+        for idx in listed {
+            let item = &self.items[idx];
+            if all && current_list != Some(&item.list) {
+                lines.push(format!("== {} ==", item.list));
+                current_list = Some(&item.list);
+            }
+            let mut line = format!("{:>4} [{}] {}{}", item.id, if item.done { 'x' } else { ' ' }, "  ".repeat(depths[idx]), item.text);
+            if item.priority != Priority::None {
+                line.push_str(&format!(" !{}", item.priority.name()));
+            }
+            if let Some(due) = item.due {
+                line.push_str(&format!(" due:{}", due));
+            }
+            lines.push(line);
+        }
+        if lines.is_empty() {
+            lines.push("No items".to_string());
+        }
+        Ok(lines.join("\n"))
+    }
+
+    fn clear_done_items(&mut self) -> String {
+        // Done items of the active list go with their subtasks, unless one of those is still open
+        let before = self.snapshot();
+        let mut removed = 0;
+        while let Some(idx) = (0..self.items.len()).find(|&idx| {
+            let item = &self.items[idx];
+            item.done && item.list == self.active_list && self.items[idx..self.subtree_end(idx)].iter().all(|item| item.done)
+        }) {
+            removed += self.subtree_end(idx) - idx;
+            self.remove_subtree(idx);
+        }
+        if self.record_change(before) {
+            self.save_todos();
+        }
+        match removed {
+            1 => "Deleted 1 done item".to_string(),
+            count => format!("Deleted {} done items", count),
+        }
+    }
+}
+
+fn rebase_snapshot(
+    mut step: Snapshot,
+    before: &Snapshot,
+    items: &[TodoItem],
+    lists: &[ListMeta],
+    renumbered: &HashMap<usize, usize>,
+) -> Option<Snapshot> {
+    // The step's changes relative to `before` are merged onto the current items; None if they
+    // collide with what changed elsewhere
+    let renumber = |id: usize| renumbered.get(&id).copied().unwrap_or(id);
+    for item in &mut step.items {
+        item.id = renumber(item.id);
+        item.parent = item.parent.map(renumber);
+    }
+    step.selected_id = step.selected_id.map(renumber);
+
+    let merged = merge::merge(&before.items, &step.items, items, step.next_id);
+    if merged.conflicts > 0 || !merged.renumbered.is_empty() {
+        return None;
+    }
+    step.items = merged.items;
+
+    // Lists are merged as a whole, the same way merge_external_changes does
+    if step.lists == before.lists {
+        step.lists = lists.to_vec();
+    } else if lists != before.lists.as_slice() && lists != step.lists.as_slice() {
+        return None;
+    }
+    Some(step)
+}
+
+fn content_hash(data: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    data.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn items_fingerprint(items: &[TodoItem]) -> u64 {
+    // Independent of display order, which depends on the sort mode. DefaultHasher isn't stable
+    // across Rust releases, which at worst drops the saved history.
+    let mut by_id: Vec<&TodoItem> = items.iter().collect();
+    by_id.sort_by_key(|item| item.id);
+    let mut hasher = DefaultHasher::new();
+    serde_json::to_string(&by_id).unwrap_or_default().hash(&mut hasher);
+    hasher.finish()
+}
+
+fn append_subtree(items: &[TodoItem], parent: Option<usize>, placed: &mut [bool], tree_order: &mut Vec<usize>) {
+    // Items are already in sibling order, so a stable scan keeps that order within each level
+    for (idx, item) in items.iter().enumerate() {
+        if item.parent == parent && !placed[idx] {
+            placed[idx] = true;
+            tree_order.push(idx);
+            append_subtree(items, Some(item.id), placed, tree_order);
+        }
+    }
+}
+
+fn tag_spans(text: &str) -> Vec<(usize, usize)> {
+    // Byte ranges of `#tag` words, including the `#`; purely numeric ones like `#412` are issue refs
+    let mut spans = Vec::new();
+    let mut previous = None;
+
+    for (start, c) in text.char_indices() {
+        if c == '#' && previous.is_none_or(char::is_whitespace) {
+            let name_len: usize = text[start + 1..]
+                .chars()
+                .take_while(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '/'))
+                .map(char::len_utf8)
+                .sum();
+            let name = &text[start + 1..start + 1 + name_len];
+            if !name.is_empty() && !name.chars().all(|c| c.is_ascii_digit()) {
+                spans.push((start, start + 1 + name_len));
+            }
+        }
+        previous = Some(c);
+    }
+
+    spans
+}
+
+fn parse_tags(text: &str) -> Vec<String> {
+    let mut tags: Vec<String> = Vec::new();
+    for (start, end) in tag_spans(text) {
+        let tag = text[start + 1..end].to_lowercase();
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+    tags
+}
+
+fn find_matches(text: &str, query: &str, case_sensitive: bool) -> Vec<(usize, usize)> {
+    // Byte ranges of non-overlapping occurrences of the query, comparing char by char so
+    // case folding never shifts the offsets
+    let query: Vec<char> = query.chars().collect();
+    let chars_equal = |a: char, b: char| {
+        if case_sensitive {
+            a == b
+        } else {
+            a == b || a.to_lowercase().eq(b.to_lowercase())
+        }
+    };
+
+    let mut matches = Vec::new();
+    let mut search_from = 0;
+    for (start, _) in text.char_indices() {
+        if start < search_from {
+            continue;
+        }
+        let mut text_chars = text[start..].char_indices();
+        let is_match = query.iter().all(|&q| text_chars.next().is_some_and(|(_, c)| chars_equal(c, q)));
+        if is_match && !query.is_empty() {
+            let end = text_chars.next().map(|(offset, _)| start + offset).unwrap_or(text.len());
+            matches.push((start, end));
+            search_from = end;
+        }
+    }
+    matches
+}
+
+fn highlight_spans(text: &str, search_spans: &[(usize, usize)], restore_style: &str) -> String {
+    // Search matches win over commands, commands over file references, and all of them over
+    // tags where they overlap
+    let mut spans: Vec<(usize, usize, &str)> = search_spans.iter().map(|&(start, end)| (start, end, "\x1b[30;43m")).collect();
+    let reference_spans = reference::find(text).into_iter().map(|(start, end, _)| (start, end)).collect();
+    for (styled_spans, style) in [
+        (command::command_spans(text), "\x1b[32m"),
+        (reference_spans, "\x1b[35m"),
+        (tag_spans(text), "\x1b[36m"),
+    ] {
+        for (start, end) in styled_spans {
+            if !spans.iter().any(|&(span_start, span_end, _)| start < span_end && span_start < end) {
+                spans.push((start, end, style));
+            }
+        }
+    }
+    spans.sort_by_key(|&(start, _, _)| start);
+
+    let mut highlighted = String::with_capacity(text.len());
+    let mut last_end = 0;
+    for (start, end, style) in spans {
+        highlighted.push_str(&text[last_end..start]);
+        highlighted.push_str(&format!("{}{}\x1b[0m{}", style, &text[start..end], restore_style));
+        last_end = end;
+    }
+    highlighted.push_str(&text[last_end..]);
+    highlighted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(id: usize, text: &str, parent: Option<usize>, display_order: usize) -> TodoItem {
+        TodoItem {
+            id,
+            text: text.to_string(),
+            parent,
+            display_order,
+            list: schema::DEFAULT_LIST.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn turns_markdown_indentation_into_depth() {
+        let items = State::default().parse_markdown_list("- Trip\n  - Book hotel\n    1. Compare prices\n  - [x] Pack\n\t* Tabbed\n- Home\nnot a list line\n");
+        let parsed: Vec<(&str, usize, bool)> = items.iter().map(|item| (item.text.as_str(), item.depth, item.done)).collect();
+        assert_eq!(
+            parsed,
+            [
+                ("Trip", 0, false),
+                ("Book hotel", 1, false),
+                ("Compare prices", 2, false),
+                ("Pack", 1, true),
+                // A tab counts as four spaces, so this nests under the item indented by two
+                ("Tabbed", 2, false),
+                ("Home", 0, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn sorts_children_right_after_their_parent() {
+        let mut state = State {
+            active_list: schema::DEFAULT_LIST.to_string(),
+            ..Default::default()
+        };
+        let mut done_parent = item(1, "done parent", None, 1);
+        done_parent.done = true;
+        let mut done_child = item(4, "done child", Some(0), 3);
+        done_child.done = true;
+        state.items = vec![
+            item(5, "child of done", Some(1), 0),
+            done_child,
+            item(3, "grandchild", Some(2), 6),
+            done_parent,
+            item(2, "child", Some(0), 5),
+            item(0, "parent", None, 2),
+            item(6, "second", None, 7),
+        ];
+        state.normalize_lists();
+        state.sort_items();
+
+        let order: Vec<&str> = state.items.iter().map(|item| item.text.as_str()).collect();
+        assert_eq!(order, ["parent", "child", "grandchild", "done child", "second", "done parent", "child of done"]);
+        assert_eq!(state.item_depths(), [0, 1, 2, 1, 0, 0, 1]);
+    }
+
+    #[test]
+    fn reads_priority_markers_at_either_end() {
+        assert_eq!(State::extract_priority("(A) Call Bob"), ("Call Bob".to_string(), Priority::Urgent));
+        assert_eq!(State::extract_priority("Call Bob (D)"), ("Call Bob".to_string(), Priority::Low));
+        assert_eq!(State::extract_priority("!! Pay rent"), ("Pay rent".to_string(), Priority::Medium));
+        assert_eq!(State::extract_priority("Pay rent !!!"), ("Pay rent".to_string(), Priority::High));
+        assert_eq!(State::extract_priority("Fix prod !!!!!"), ("Fix prod".to_string(), Priority::Urgent));
+        // Markers in the middle, lowercase letters and lone markers stay part of the text
+        assert_eq!(State::extract_priority("Ask (A) first"), ("Ask (A) first".to_string(), Priority::None));
+        assert_eq!(State::extract_priority("(a) item"), ("(a) item".to_string(), Priority::None));
+        assert_eq!(State::extract_priority("Wow!!"), ("Wow!!".to_string(), Priority::None));
+        assert_eq!(State::extract_priority("!!"), ("!!".to_string(), Priority::None));
+    }
+
+    #[test]
+    fn pastes_priorities_with_checkboxes() {
+        let items = State::default().parse_markdown_list("- [x] (B) Done high\n- Low one !");
+        let parsed: Vec<(&str, bool, Priority)> = items.iter().map(|item| (item.text.as_str(), item.done, item.priority)).collect();
+        assert_eq!(parsed, [("Done high", true, Priority::High), ("Low one", false, Priority::Low)]);
+    }
+
+    #[test]
+    fn finds_tags_but_not_issue_numbers() {
+        assert_eq!(parse_tags("Fix #412 for #Work and #home/garden, #work again"), ["work", "home/garden"]);
+        assert_eq!(tag_spans("a#b #c-d #日本 #"), [(4, 8), (9, 16)]);
+        assert!(parse_tags("issue #12 and #3").is_empty());
+        // Digits count when there is more to the name
+        assert_eq!(parse_tags("#2024q1"), ["2024q1"]);
+    }
+
+    #[test]
+    fn matches_search_queries_with_case_folding() {
+        assert_eq!(find_matches("Buy milk, BUY bread", "buy", false), [(0, 3), (10, 13)]);
+        assert_eq!(find_matches("Buy milk, BUY bread", "buy", true), []);
+        assert_eq!(find_matches("Buy milk, BUY bread", "BUY", true), [(10, 13)]);
+        // Offsets stay on the original text when folding changes a character's length
+        assert_eq!(find_matches("İstanbul ÉTÉ été", "été", false), [(10, 15), (16, 21)]);
+        assert_eq!(find_matches("aaaa", "aa", false), [(0, 2), (2, 4)]);
+        assert_eq!(find_matches("text", "", false), []);
     }
 }
@@ -0,0 +1,200 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{parse_tags, TodoItem};
+
+pub struct Merged {
+    pub items: Vec<TodoItem>,
+    /// Number of items edited on both sides in incompatible ways; our version was kept.
+    pub conflicts: usize,
+    /// Our items that had to be given a new id because the other side added one with the same id.
+    pub renumbered: HashMap<usize, usize>,
+}
+
+/// Three-way merge of the items we have in memory (`ours`) with the items found on disk
+/// (`theirs`), relative to the items we last loaded or saved (`base`). Items are matched by
+/// id and merged field by field; when both sides changed the same field differently, our
+/// change wins and the item counts as a conflict.
+pub fn merge(base: &[TodoItem], ours: &[TodoItem], theirs: &[TodoItem], next_id: usize) -> Merged {
+    let base_by_id: HashMap<usize, &TodoItem> = base.iter().map(|item| (item.id, item)).collect();
+    let theirs_by_id: HashMap<usize, &TodoItem> = theirs.iter().map(|item| (item.id, item)).collect();
+
+    // Both sides may have handed out the same fresh id to different new items - renumber ours
+    let mut renumbered = HashMap::new();
+    let mut next_id = theirs.iter().chain(ours).map(|item| item.id + 1).fold(next_id, usize::max);
+    for item in ours {
+        if !base_by_id.contains_key(&item.id) && theirs_by_id.contains_key(&item.id) {
+            renumbered.insert(item.id, next_id);
+            next_id += 1;
+        }
+    }
+    let ours: Vec<TodoItem> = ours
+        .iter()
+        .cloned()
+        .map(|mut item| {
+            if let Some(&id) = renumbered.get(&item.id) {
+                item.id = id;
+            }
+            if let Some(&parent) = item.parent.as_ref().and_then(|parent| renumbered.get(parent)) {
+                item.parent = Some(parent);
+            }
+            item
+        })
+        .collect();
+    let ours_by_id: HashMap<usize, &TodoItem> = ours.iter().map(|item| (item.id, item)).collect();
+
+    let mut items = Vec::new();
+    let mut conflicts = 0;
+    let mut seen = HashSet::new();
+
+    for id in theirs.iter().chain(ours.iter()).map(|item| item.id) {
+        if !seen.insert(id) {
+            continue;
+        }
+
+        let merged = match (base_by_id.get(&id), ours_by_id.get(&id), theirs_by_id.get(&id)) {
+            (Some(base), Some(ours), Some(theirs)) => {
+                let (item, conflicted) = merge_item(base, ours, theirs);
+                conflicts += conflicted as usize;
+                Some(item)
+            }
+            // Deleted on disk: drop it, unless we edited it in the meantime
+            (Some(base), Some(ours), None) => {
+                if *ours == *base {
+                    None
+                } else {
+                    conflicts += 1;
+                    Some((*ours).clone())
+                }
+            }
+            // Deleted by us: keep it deleted, unless it was edited on disk in the meantime
+            (Some(base), None, Some(theirs)) => {
+                if *theirs == *base {
+                    None
+                } else {
+                    conflicts += 1;
+                    Some((*theirs).clone())
+                }
+            }
+            (Some(_), None, None) => None,
+            // New on one side only (collisions were renumbered above)
+            (None, Some(item), None) | (None, None, Some(item)) => Some((*item).clone()),
+            (None, Some(_), Some(theirs)) => Some((*theirs).clone()),
+            (None, None, None) => None,
+        };
+
+        items.extend(merged);
+    }
+
+    Merged {
+        items,
+        conflicts,
+        renumbered,
+    }
+}
+
+fn merge_item(base: &TodoItem, ours: &TodoItem, theirs: &TodoItem) -> (TodoItem, bool) {
+    let mut conflicted = false;
+    let mut item = TodoItem {
+        text: merge_field(&base.text, &ours.text, &theirs.text, &mut conflicted),
+        done: merge_field(&base.done, &ours.done, &theirs.done, &mut conflicted),
+        id: ours.id,
+        display_order: merge_field(&base.display_order, &ours.display_order, &theirs.display_order, &mut conflicted),
+        list: merge_field(&base.list, &ours.list, &theirs.list, &mut conflicted),
+        parent: merge_field(&base.parent, &ours.parent, &theirs.parent, &mut conflicted),
+        collapsed: merge_field(&base.collapsed, &ours.collapsed, &theirs.collapsed, &mut conflicted),
+        priority: merge_field(&base.priority, &ours.priority, &theirs.priority, &mut conflicted),
+        due: merge_field(&base.due, &ours.due, &theirs.due, &mut conflicted),
+        notes: merge_field(&base.notes, &ours.notes, &theirs.notes, &mut conflicted),
+        tags: Vec::new(),
+        extra: merge_field(&base.extra, &ours.extra, &theirs.extra, &mut conflicted),
+    };
+    item.tags = parse_tags(&item.text);
+    (item, conflicted)
+}
+
+fn merge_field<T: PartialEq + Clone>(base: &T, ours: &T, theirs: &T, conflicted: &mut bool) -> T {
+    if ours == base {
+        theirs.clone()
+    } else {
+        if theirs != base && theirs != ours {
+            *conflicted = true;
+        }
+        ours.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(id: usize, text: &str) -> TodoItem {
+        TodoItem {
+            id,
+            text: text.to_string(),
+            display_order: id,
+            ..Default::default()
+        }
+    }
+
+    fn texts(merged: &Merged) -> Vec<(usize, &str)> {
+        let mut texts: Vec<(usize, &str)> = merged.items.iter().map(|item| (item.id, item.text.as_str())).collect();
+        texts.sort();
+        texts
+    }
+
+    #[test]
+    fn combines_edits_to_different_fields() {
+        let base = [item(0, "a")];
+        let ours = [item(0, "a renamed")];
+        let mut theirs = [item(0, "a")];
+        theirs[0].done = true;
+
+        let merged = merge(&base, &ours, &theirs, 1);
+        assert_eq!(merged.conflicts, 0);
+        assert_eq!(merged.items[0].text, "a renamed");
+        assert!(merged.items[0].done);
+    }
+
+    #[test]
+    fn keeps_ours_when_both_sides_change_a_field() {
+        let base = [item(0, "a"), item(1, "b")];
+        let ours = [item(0, "ours #mine"), item(1, "b")];
+        let theirs = [item(0, "theirs"), item(1, "b")];
+
+        let merged = merge(&base, &ours, &theirs, 2);
+        assert_eq!(merged.conflicts, 1);
+        assert_eq!(texts(&merged), [(0, "ours #mine"), (1, "b")]);
+        assert_eq!(merged.items.iter().find(|item| item.id == 0).unwrap().tags, ["mine"]);
+
+        // The same change on both sides is no conflict
+        let merged = merge(&base, &theirs, &theirs, 2);
+        assert_eq!(merged.conflicts, 0);
+    }
+
+    #[test]
+    fn follows_deletions_unless_the_item_was_edited() {
+        let base = [item(0, "a"), item(1, "b"), item(2, "c"), item(3, "d")];
+        // We deleted 0 and 1 and edited 2; they deleted 2 and 3 and edited 1
+        let ours = [item(2, "c edited"), item(3, "d")];
+        let theirs = [item(0, "a"), item(1, "b edited")];
+
+        let merged = merge(&base, &ours, &theirs, 4);
+        assert_eq!(merged.conflicts, 2);
+        assert_eq!(texts(&merged), [(1, "b edited"), (2, "c edited")]);
+    }
+
+    #[test]
+    fn renumbers_our_new_items_that_collide() {
+        let base = [item(0, "a")];
+        let mut child = item(2, "our child");
+        child.parent = Some(1);
+        let ours = [item(0, "a"), item(1, "our new"), child];
+        let theirs = [item(0, "a"), item(1, "their new")];
+
+        let merged = merge(&base, &ours, &theirs, 2);
+        assert_eq!(merged.conflicts, 0);
+        assert_eq!(texts(&merged), [(0, "a"), (1, "their new"), (2, "our child"), (3, "our new")]);
+        assert_eq!(merged.renumbered, HashMap::from([(1, 3)]));
+        assert_eq!(merged.items.iter().find(|item| item.id == 2).unwrap().parent, Some(3));
+    }
+}
@@ -0,0 +1,89 @@
+use serde::Deserialize;
+
+/// Newest version of the message set below that this build understands. Bump it when a message
+/// changes meaning; new messages and new optional payload fields don't need a new version.
+///
+/// Version 1:
+/// - `zellij-todo:toggle` hides the plugin. The payload may be empty.
+/// - `zellij-todo:paste` handles `text` as if it was pasted into the current mode.
+/// - `zellij-todo:add` adds `text` as an item at the end of the active list.
+pub const MESSAGE_VERSION: u64 = 1;
+
+/// A message from another plugin, a keybinding or a worker.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Toggle,
+    Paste(String),
+    Add(String),
+}
+
+/// The JSON payload every message carries. A missing version means version 1.
+#[derive(Debug, Default, Deserialize)]
+struct Payload {
+    #[serde(default = "first_version")]
+    version: u64,
+    #[serde(default)]
+    text: Option<String>,
+}
+
+fn first_version() -> u64 {
+    1
+}
+
+/// Reads a message sent under `name` with a JSON `payload`. Names that aren't ours give
+/// `Ok(None)`, since other plugins' broadcasts reach us too.
+pub fn parse(name: &str, payload: &str) -> Result<Option<Message>, String> {
+    let Some(kind) = name.strip_prefix("zellij-todo:") else {
+        return Ok(None);
+    };
+    let payload: Payload = if payload.trim().is_empty() {
+        Payload {
+            version: first_version(),
+            ..Default::default()
+        }
+    } else {
+        serde_json::from_str(payload).map_err(|error| format!("{}: {}", name, error))?
+    };
+    if payload.version > MESSAGE_VERSION {
+        return Err(format!("{}: message version {} is newer than this plugin supports", name, payload.version));
+    }
+
+    let text = || payload.text.clone().filter(|text| !text.trim().is_empty()).ok_or_else(|| format!("{}: missing \"text\"", name));
+    match kind {
+        "toggle" => Ok(Some(Message::Toggle)),
+        "paste" => Ok(Some(Message::Paste(text()?))),
+        "add" => Ok(Some(Message::Add(text()?))),
+        _ => Err(format!("{}: unknown message", name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_our_messages() {
+        assert_eq!(parse("zellij-todo:toggle", ""), Ok(Some(Message::Toggle)));
+        assert_eq!(parse("zellij-todo:toggle", r#"{"version": 1}"#), Ok(Some(Message::Toggle)));
+        assert_eq!(parse("zellij-todo:add", r#"{"text": "Buy milk"}"#), Ok(Some(Message::Add("Buy milk".to_string()))));
+        assert_eq!(
+            parse("zellij-todo:paste", r#"{"version": 1, "text": "- a\n- b", "later": true}"#),
+            Ok(Some(Message::Paste("- a\n- b".to_string())))
+        );
+    }
+
+    #[test]
+    fn ignores_other_plugins_messages() {
+        assert_eq!(parse("toggle", ""), Ok(None));
+        assert_eq!(parse("other:zellij-todo:toggle", "{"), Ok(None));
+    }
+
+    #[test]
+    fn rejects_bad_messages() {
+        assert!(parse("zellij-todo:add", "").unwrap_err().contains("missing \"text\""));
+        assert!(parse("zellij-todo:add", r#"{"text": "  "}"#).is_err());
+        assert!(parse("zellij-todo:toggle", "toggle").is_err());
+        assert!(parse("zellij-todo:toggle", r#"{"version": 2}"#).unwrap_err().contains("newer"));
+        assert!(parse("zellij-todo:remove", "").unwrap_err().contains("unknown message"));
+    }
+}
@@ -0,0 +1,110 @@
+use zellij_tile::prelude::*;
+
+use crate::line_editor::LineEditor;
+use crate::text;
+
+/// The keys `NotesEditor::handle_key` adds to those of `LineEditor`, as listed in the help.
+pub const KEYS: &[(&str, &str)] = &[
+    ("Enter", "Start a new line"),
+    ("↑ / ↓", "Move between lines"),
+    ("Backspace / Delete", "Join with the line above/below at the start/end of a line"),
+];
+
+/// Multi-line text with a cursor, as edited in the notes editor. Every line is a `LineEditor`,
+/// so editing within a line works the same as in Edit mode.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NotesEditor {
+    lines: Vec<LineEditor>,
+    // Line the cursor is on
+    row: usize,
+}
+
+impl Default for NotesEditor {
+    fn default() -> NotesEditor {
+        NotesEditor::new("")
+    }
+}
+
+impl NotesEditor {
+    /// Starts editing `text` with the cursor at the end.
+    pub fn new(text: &str) -> NotesEditor {
+        let lines: Vec<LineEditor> = text.split('\n').map(|line| LineEditor::new(line.to_string())).collect();
+        NotesEditor {
+            row: lines.len() - 1,
+            lines,
+        }
+    }
+
+    /// The lines joined with newlines, without trailing blank lines.
+    pub fn text(&self) -> String {
+        let lines: Vec<&str> = self.lines.iter().map(LineEditor::text).collect();
+        lines.join("\n").trim_end().to_string()
+    }
+
+    /// Handles an editing or cursor key, returning false for keys that aren't ours.
+    pub fn handle_key(&mut self, key: &KeyWithModifier) -> bool {
+        let line = &self.lines[self.row];
+
+        match key.bare_key {
+            BareKey::Enter if key.has_no_modifiers() => self.insert("\n"),
+            BareKey::Up if key.has_no_modifiers() && self.row > 0 => self.move_to_row(self.row - 1),
+            BareKey::Down if key.has_no_modifiers() && self.row + 1 < self.lines.len() => {
+                self.move_to_row(self.row + 1)
+            }
+
+            // Deleting across the start or end of a line joins it with its neighbour
+            BareKey::Backspace if key.has_no_modifiers() && self.row > 0 && line.cursor_at_start() => {
+                let line = self.lines.remove(self.row);
+                self.row -= 1;
+                let mut joined = LineEditor::new(self.lines[self.row].text().to_string());
+                joined.push_str(line.text());
+                self.lines[self.row] = joined;
+            }
+            BareKey::Delete if key.has_no_modifiers() && self.row + 1 < self.lines.len() && line.cursor_at_end() => {
+                let next = self.lines.remove(self.row + 1);
+                self.lines[self.row].push_str(next.text());
+            }
+
+            _ => return self.lines[self.row].handle_key(key),
+        }
+        true
+    }
+
+    /// Inserts `text` at the cursor, breaking the line at every newline in it.
+    pub fn insert(&mut self, text: &str) {
+        for (idx, part) in text.split('\n').enumerate() {
+            if idx > 0 {
+                let rest = self.lines[self.row].split_off();
+                let mut line = LineEditor::default();
+                line.push_str(&rest);
+                self.row += 1;
+                self.lines.insert(self.row, line);
+            }
+            self.lines[self.row].insert(part.trim_end_matches('\r'));
+        }
+    }
+
+    /// Up to `height` lines of at most `max_width` columns, scrolled so the cursor is in view.
+    pub fn render(&self, max_width: usize, height: usize) -> Vec<String> {
+        let start = (self.row + 1).saturating_sub(height);
+        self.lines
+            .iter()
+            .enumerate()
+            .skip(start)
+            .take(height)
+            .map(|(row, line)| {
+                if row == self.row {
+                    line.render(max_width)
+                } else {
+                    text::truncate(line.text(), max_width)
+                }
+            })
+            .collect()
+    }
+
+    fn move_to_row(&mut self, row: usize) {
+        let column = self.lines[self.row].cursor_column();
+        self.row = row;
+        self.lines[row].set_cursor_column(column);
+    }
+}
@@ -0,0 +1,131 @@
+/// Usage shown for `help` and unknown commands sent with `zellij pipe`.
+pub const USAGE: &str = "Commands:
+  add <text>          Add an item to the active list (due: words are understood,
+                      further lines become its notes)
+  done <id>...        Mark items done
+  list [--json] [--all]
+                      List the active list, or every list with --all
+  clear-done          Delete the done items of the active list
+  help                Show this help";
+
+/// A command sent with `zellij pipe`, either as the whole payload (`-- "done 4"`) or as the
+/// pipe name with the payload as its argument (`--name add -- "Buy milk"`).
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Add(String),
+    Done(Vec<usize>),
+    List { json: bool, all: bool },
+    ClearDone,
+    Help,
+}
+
+pub fn parse(name: &str, payload: &str) -> Result<Command, String> {
+    // Pipes without a name get a random one, so only known command names are taken as such
+    let (command, argument) = if is_command(name) {
+        (name, payload.trim())
+    } else {
+        let payload = payload.trim();
+        payload.split_once(char::is_whitespace).map(|(command, rest)| (command, rest.trim())).unwrap_or((payload, ""))
+    };
+
+    match command {
+        "add" => {
+            // Lines after the first become the item's notes
+            let (first_line, notes) = argument.split_once('\n').unwrap_or((argument, ""));
+            let text = unquote(first_line.trim());
+            if text.is_empty() {
+                Err("add needs the text of the item".to_string())
+            } else if notes.trim().is_empty() {
+                Ok(Command::Add(text.to_string()))
+            } else {
+                Ok(Command::Add(format!("{}\n{}", text, notes)))
+            }
+        }
+        "done" => {
+            let ids = argument
+                .split(|c: char| c.is_whitespace() || c == ',')
+                .filter(|word| !word.is_empty())
+                .map(|word| word.trim_start_matches('#').parse().map_err(|_| format!("not an item id: {}", word)))
+                .collect::<Result<Vec<usize>, String>>()?;
+            if ids.is_empty() {
+                Err("done needs at least one item id (see list)".to_string())
+            } else {
+                Ok(Command::Done(ids))
+            }
+        }
+        "list" => {
+            let mut json = false;
+            let mut all = false;
+            for flag in argument.split_whitespace() {
+                match flag {
+                    "--json" => json = true,
+                    "--all" => all = true,
+                    _ => return Err(format!("unknown option for list: {}", flag)),
+                }
+            }
+            Ok(Command::List { json, all })
+        }
+        "clear-done" if argument.is_empty() => Ok(Command::ClearDone),
+        "clear-done" => Err("clear-done takes no arguments".to_string()),
+        "help" | "" => Ok(Command::Help),
+        _ => Err(format!("unknown command: {}\n{}", command, USAGE)),
+    }
+}
+
+fn is_command(name: &str) -> bool {
+    matches!(name, "add" | "done" | "list" | "clear-done" | "help")
+}
+
+fn unquote(text: &str) -> &str {
+    // Shells hand over the quotes when the whole command is one argument: -- 'add "Buy milk"'
+    for quote in ['"', '\''] {
+        if let Some(inner) = text.strip_prefix(quote).and_then(|rest| rest.strip_suffix(quote)) {
+            return inner;
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_command_from_the_payload_or_the_name() {
+        assert_eq!(parse("a1b2c3", "add Buy milk"), Ok(Command::Add("Buy milk".to_string())));
+        assert_eq!(parse("add", "Buy milk"), Ok(Command::Add("Buy milk".to_string())));
+        assert_eq!(parse("a1b2c3", "  done 4  "), Ok(Command::Done(vec![4])));
+        assert_eq!(parse("a1b2c3", ""), Ok(Command::Help));
+        assert_eq!(parse("help", ""), Ok(Command::Help));
+    }
+
+    #[test]
+    fn unquotes_the_text_and_keeps_notes() {
+        assert_eq!(parse("x", r#"add "Buy milk""#), Ok(Command::Add("Buy milk".to_string())));
+        assert_eq!(parse("x", "add 'it''s'"), Ok(Command::Add("it''s".to_string())));
+        assert_eq!(parse("add", "\"Call\" Bob"), Ok(Command::Add("\"Call\" Bob".to_string())));
+        assert_eq!(parse("add", "Plan trip\n- book hotel\n"), Ok(Command::Add("Plan trip\n- book hotel".to_string())));
+        assert_eq!(parse("add", "Plan trip\n  \n"), Ok(Command::Add("Plan trip".to_string())));
+        assert!(parse("add", "  ").is_err());
+        assert!(parse("add", "\"\"").is_err());
+    }
+
+    #[test]
+    fn parses_ids_and_flags() {
+        assert_eq!(parse("done", "1, #2 3"), Ok(Command::Done(vec![1, 2, 3])));
+        assert_eq!(parse("done", "1 two"), Err("not an item id: two".to_string()));
+        assert!(parse("done", "").is_err());
+        assert_eq!(parse("list", ""), Ok(Command::List { json: false, all: false }));
+        assert_eq!(parse("x", "list --all --json"), Ok(Command::List { json: true, all: true }));
+        assert!(parse("list", "--yaml").is_err());
+        assert_eq!(parse("x", "clear-done"), Ok(Command::ClearDone));
+        assert!(parse("clear-done", "now").is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_commands_with_usage() {
+        let error = parse("x", "remove 3").unwrap_err();
+        assert!(error.starts_with("unknown command: remove\n"), "{}", error);
+        assert!(error.ends_with(USAGE));
+    }
+}
@@ -0,0 +1,199 @@
+/// Shell script run on the host to find the todo file for a directory. Walks up from `$1`
+/// looking for a file named `$2`, stopping at the git root, and prints `file <dir>` for the
+/// nearest one, `root <dir>` if the git root was reached without finding one, or nothing.
+pub const FIND_TODO_FILE_SCRIPT: &str = r#"dir=$1
+while :; do
+    if [ -f "$dir/$2" ]; then echo "file $dir"; exit 0; fi
+    if [ -e "$dir/.git" ]; then echo "root $dir"; exit 0; fi
+    if [ "$dir" = / ] || [ -z "$dir" ]; then exit 0; fi
+    dir=$(dirname "$dir")
+done"#;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Discovery {
+    // Directory holding the nearest todo file
+    File(String),
+    // Git root without a todo file
+    GitRoot(String),
+    // Neither, e.g. outside any repository
+    Nothing,
+}
+
+pub fn parse_discovery(output: &str) -> Discovery {
+    let output = output.trim();
+    if let Some(dir) = output.strip_prefix("file ") {
+        Discovery::File(dir.to_string())
+    } else if let Some(dir) = output.strip_prefix("root ") {
+        Discovery::GitRoot(dir.to_string())
+    } else {
+        Discovery::Nothing
+    }
+}
+
+/// Working directory of the focused terminal pane in the focused tab, read from the KDL that
+/// `dump_session_layout` produces. Plugin panes (including this one) are skipped, so a floating
+/// todo pane still finds the terminal underneath it.
+pub fn focused_pane_cwd(layout: &str) -> Option<String> {
+    let mut layout_cwd = String::from("/");
+    let mut depth = 0;
+    // Depth and cwd of the focused tab while we are inside it
+    let mut focused_tab: Option<(usize, String)> = None;
+    // Focused pane waiting to be ruled out as a plugin by its first child line
+    let mut candidate: Option<(usize, String)> = None;
+
+    for line in layout.lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some((candidate_depth, cwd)) = candidate.take() {
+            if depth == candidate_depth + 1 && line.starts_with("plugin ") {
+                // A plugin pane - keep looking
+            } else {
+                return Some(cwd);
+            }
+        }
+
+        if line == "}" {
+            depth = depth.saturating_sub(1);
+            if focused_tab.as_ref().is_some_and(|(tab_depth, _)| depth <= *tab_depth) {
+                focused_tab = None;
+            }
+            continue;
+        }
+
+        if depth == 1 {
+            if let Some(cwd) = line.strip_prefix("cwd ").and_then(quoted) {
+                layout_cwd = cwd;
+            }
+        }
+
+        let is_focused = line.split_whitespace().any(|word| word == "focus=true");
+        if line.starts_with("tab ") && is_focused && focused_tab.is_none() {
+            let cwd = attribute(line, "cwd").map(|cwd| join(&layout_cwd, &cwd)).unwrap_or_else(|| layout_cwd.clone());
+            focused_tab = Some((depth, cwd));
+        } else if let Some((_, tab_cwd)) = &focused_tab {
+            if (line == "pane" || line.starts_with("pane ")) && is_focused {
+                let cwd = attribute(line, "cwd").map(|cwd| join(tab_cwd, &cwd)).unwrap_or_else(|| tab_cwd.clone());
+                if line.ends_with('{') {
+                    candidate = Some((depth, cwd));
+                } else {
+                    return Some(cwd);
+                }
+            }
+        }
+
+        if line.ends_with('{') {
+            depth += 1;
+        }
+    }
+
+    candidate.map(|(_, cwd)| cwd)
+}
+
+fn attribute(line: &str, name: &str) -> Option<String> {
+    let start = line.find(&format!(" {}=", name))? + name.len() + 2;
+    quoted(&line[start..])
+}
+
+fn quoted(text: &str) -> Option<String> {
+    let text = text.trim().strip_prefix('"')?;
+    let end = text.find('"')?;
+    Some(text[..end].to_string())
+}
+
+fn join(base: &str, path: &str) -> String {
+    if path.starts_with('/') {
+        path.to_string()
+    } else {
+        format!("{}/{}", base.trim_end_matches('/'), path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_discovery_output() {
+        assert_eq!(parse_discovery("file /home/me/project\n"), Discovery::File("/home/me/project".to_string()));
+        assert_eq!(parse_discovery("root /home/me/repo"), Discovery::GitRoot("/home/me/repo".to_string()));
+        assert_eq!(parse_discovery(""), Discovery::Nothing);
+        assert_eq!(parse_discovery("  \n"), Discovery::Nothing);
+    }
+
+    #[test]
+    fn skips_a_focused_floating_plugin_pane() {
+        let layout = r#"layout {
+    cwd "/home/me"
+    tab name="Tab #1" focus=true hide_floating_panes=false {
+        pane size=1 borderless=true {
+            plugin location="zellij:tab-bar"
+        }
+        pane command="cargo" cwd="project" focus=true {
+            args "watch"
+        }
+        floating_panes {
+            pane focus=true {
+                plugin location="file:/plugins/zellij-todo.wasm"
+            }
+        }
+    }
+}"#;
+        assert_eq!(focused_pane_cwd(layout), Some("/home/me/project".to_string()));
+
+        // The same with the floating todo pane listed first
+        let layout = r#"layout {
+    tab focus=true {
+        floating_panes {
+            pane focus=true {
+                plugin location="file:/plugins/zellij-todo.wasm"
+            }
+        }
+        pane cwd="/srv/app" focus=true
+    }
+}"#;
+        assert_eq!(focused_pane_cwd(layout), Some("/srv/app".to_string()));
+    }
+
+    #[test]
+    fn joins_relative_cwds_onto_the_tab_and_layout() {
+        let layout = r#"layout {
+    cwd "/home/me"
+    tab name="one" {
+        pane cwd="elsewhere" focus=true
+    }
+    tab name="two" cwd="code" focus=true {
+        pane split_direction="vertical" {
+            pane
+            pane cwd="site/" focus=true
+        }
+    }
+}"#;
+        assert_eq!(focused_pane_cwd(layout), Some("/home/me/code/site/".to_string()));
+    }
+
+    #[test]
+    fn uses_the_tab_cwd_for_a_focused_pane_without_one() {
+        let layout = r#"layout {
+    cwd "/home/me"
+    tab cwd="/tmp/work" focus=true {
+        pane focus=true
+    }
+}"#;
+        assert_eq!(focused_pane_cwd(layout), Some("/tmp/work".to_string()));
+    }
+
+    #[test]
+    fn finds_nothing_without_a_focused_tab() {
+        let layout = r#"layout {
+    cwd "/home/me"
+    tab name="one" {
+        pane cwd="a" focus=true
+    }
+}"#;
+        assert_eq!(focused_pane_cwd(layout), None);
+        assert_eq!(focused_pane_cwd(""), None);
+    }
+}
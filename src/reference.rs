@@ -0,0 +1,106 @@
+/// A file an item refers to, such as `src/main.rs:412` or `./notes/plan.md`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reference {
+    pub path: String,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+}
+
+impl Reference {
+    /// The reference as it is written, `path[:line[:column]]`.
+    pub fn label(&self) -> String {
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => format!("{}:{}:{}", self.path, line, column),
+            (Some(line), None) => format!("{}:{}", self.path, line),
+            _ => self.path.clone(),
+        }
+    }
+}
+
+/// Byte ranges of the file references in `text`, with what they refer to. A word counts as one
+/// if it ends in `:line` or `:line:column`, or looks like a path: it starts with `/`, `./` or
+/// `../`, or has a folder and a file extension. URLs don't count.
+pub fn find(text: &str) -> Vec<(usize, usize, Reference)> {
+    let mut references = Vec::new();
+    for (word_start, word) in words(text) {
+        // Leave out quotes, brackets and sentence punctuation around the reference
+        let trimmed_start = word.trim_start_matches(['(', '[', '<', '"', '\'', '`']);
+        let start = word_start + word.len() - trimmed_start.len();
+        let candidate = trimmed_start.trim_end_matches([')', ']', '>', '"', '\'', '`', '.', ',', ';', ':', '!', '?']);
+        if let Some(reference) = parse(candidate) {
+            references.push((start, start + candidate.len(), reference));
+        }
+    }
+    references
+}
+
+fn parse(candidate: &str) -> Option<Reference> {
+    if candidate.contains("://") || candidate.starts_with('#') {
+        return None;
+    }
+
+    // Peel off up to two trailing numbers: path:line or path:line:column
+    let mut path = candidate;
+    let mut numbers = Vec::new();
+    while numbers.len() < 2 {
+        match path.rsplit_once(':') {
+            Some((rest, number)) if !number.is_empty() && number.bytes().all(|byte| byte.is_ascii_digit()) => {
+                numbers.push(number.parse().ok()?);
+                path = rest;
+            }
+            _ => break,
+        }
+    }
+    numbers.reverse();
+    let (line, column) = (numbers.first().copied(), numbers.get(1).copied());
+
+    let is_rooted = ["/", "./", "../"].iter().any(|prefix| path.starts_with(prefix)) && path.len() > 2;
+    let file_name = path.rsplit('/').next().unwrap_or(path);
+    let has_extension = file_name
+        .rsplit_once('.')
+        .is_some_and(|(stem, extension)| !stem.is_empty() && !extension.is_empty() && extension.chars().all(|c| c.is_ascii_alphanumeric()) && extension.chars().any(|c| c.is_ascii_alphabetic()));
+    let looks_like_path = is_rooted || (path.contains('/') && has_extension);
+    let has_location = line.is_some() && (has_extension || path.contains('/'));
+
+    (looks_like_path || has_location).then(|| Reference {
+        path: path.to_string(),
+        line,
+        column,
+    })
+}
+
+fn words(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    // Words are slices of `text`, so their offsets follow from where they start
+    text.split_whitespace().map(move |word| (word.as_ptr() as usize - text.as_ptr() as usize, word))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(text: &str) -> Vec<String> {
+        find(text).into_iter().map(|(_, _, reference)| reference.label()).collect()
+    }
+
+    #[test]
+    fn finds_paths_with_and_without_locations() {
+        assert_eq!(labels("fix src/main.rs:412 and ./notes/plan.md"), ["src/main.rs:412", "./notes/plan.md"]);
+        assert_eq!(labels("see lib.rs:3:14, then /etc/hosts."), ["lib.rs:3:14", "/etc/hosts"]);
+        assert_eq!(labels("(docs/guide.md)"), ["docs/guide.md"]);
+    }
+
+    #[test]
+    fn leaves_out_words_that_only_look_similar() {
+        assert!(labels("https://example.com/a.rs:1 #tag 12:30 v1.2 and/or e.g. ./ a:b").is_empty());
+    }
+
+    #[test]
+    fn reports_byte_ranges_of_the_reference() {
+        let (start, end, reference) = find("  (src/a.rs:7).").remove(0);
+        assert_eq!((start, end), (3, 13));
+        assert_eq!(reference, Reference { path: "src/a.rs".to_string(), line: Some(7), column: None });
+        // Offsets are in bytes, past multi-byte characters
+        let (start, end, _) = find("café src/a.rs:7").remove(0);
+        assert_eq!((start, end), (6, 16));
+    }
+}
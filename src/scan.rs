@@ -0,0 +1,117 @@
+/// Shell script run on the host to find TODO, FIXME and HACK comments under the current folder.
+/// Inside a git work tree `git grep` searches tracked and untracked files, skipping whatever
+/// `.gitignore` ignores; elsewhere plain `grep` searches everything but `.git`. Files named
+/// like the todo file (`$1`) and its backups are left out, and the output is capped at `$2`
+/// lines. Prints `path:line:text` lines.
+pub const SCAN_SCRIPT: &str = r#"if git rev-parse --is-inside-work-tree >/dev/null 2>&1; then
+    git grep -n -I -w --untracked --exclude-standard -e TODO -e FIXME -e HACK -- . ":(exclude,glob)**/$1*"
+else
+    grep -rnIw --exclude-dir=.git --exclude="$1*" -e TODO -e FIXME -e HACK .
+fi | head -n "$2""#;
+
+/// Most comments kept from one scan.
+pub const MAX_COMMENTS: usize = 1000;
+
+const KINDS: [&str; 3] = ["TODO", "FIXME", "HACK"];
+
+/// A TODO, FIXME or HACK comment found in a source file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CodeComment {
+    pub kind: &'static str,
+    // What follows the keyword, without the usual `:` or `(name):`
+    pub text: String,
+    pub path: String,
+    pub line: usize,
+}
+
+impl CodeComment {
+    /// Where the comment is, as `path:line`.
+    pub fn location(&self) -> String {
+        format!("{}:{}", self.path, self.line)
+    }
+}
+
+pub fn parse_output(output: &str) -> Vec<CodeComment> {
+    output.lines().filter_map(parse_line).take(MAX_COMMENTS).collect()
+}
+
+fn parse_line(line: &str) -> Option<CodeComment> {
+    let (path, rest) = line.split_once(':')?;
+    let (line_number, content) = rest.split_once(':')?;
+    let line = line_number.parse().ok()?;
+
+    // The first keyword on the line that stands on its own, so `TODOS` or `MY_TODO` don't count
+    let (start, kind) = KINDS
+        .iter()
+        .filter_map(|&kind| {
+            content
+                .match_indices(kind)
+                .find(|&(start, _)| is_word_boundary(content, start, start + kind.len()))
+                .map(|(start, _)| (start, kind))
+        })
+        .min_by_key(|&(start, _)| start)?;
+
+    let mut text = content[start + kind.len()..].trim_start();
+    // Skip an owner or issue in parentheses, as in TODO(alice): or FIXME(#12)
+    if text.starts_with('(') {
+        if let Some(end) = text.find(')') {
+            text = text[end + 1..].trim_start();
+        }
+    }
+    let text = text.trim_start_matches([':', '-']).trim();
+    // Closing comment markers aren't part of the message
+    let text = text.trim_end_matches("*/").trim_end_matches("-->").trim_end();
+
+    Some(CodeComment {
+        kind,
+        text: text.to_string(),
+        path: path.strip_prefix("./").unwrap_or(path).to_string(),
+        line,
+    })
+}
+
+fn is_word_boundary(text: &str, start: usize, end: usize) -> bool {
+    let is_word_char = |c: char| c.is_alphanumeric() || c == '_';
+    !text[..start].chars().next_back().is_some_and(is_word_char) && !text[end..].chars().next().is_some_and(is_word_char)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn comment(kind: &'static str, text: &str, path: &str, line: usize) -> Option<CodeComment> {
+        Some(CodeComment {
+            kind,
+            text: text.to_string(),
+            path: path.to_string(),
+            line,
+        })
+    }
+
+    #[test]
+    fn parses_grep_lines() {
+        assert_eq!(parse_line("src/a.rs:12:    // TODO: fix parser"), comment("TODO", "fix parser", "src/a.rs", 12));
+        assert_eq!(parse_line("./b.py:3:x = 1  # FIXME(alice): overflow"), comment("FIXME", "overflow", "b.py", 3));
+        assert_eq!(parse_line("c.c:7:/* HACK - temporary */"), comment("HACK", "temporary", "c.c", 7));
+        assert_eq!(parse_line("d.html:1:<!-- TODO: x -->"), comment("TODO", "x", "d.html", 1));
+        assert_eq!(parse_line("e.rs:2:// TODO"), comment("TODO", "", "e.rs", 2));
+        // The first keyword on the line wins, and the text may hold colons of its own
+        assert_eq!(parse_line("f.rs:9:// FIXME: TODO: a:b"), comment("FIXME", "TODO: a:b", "f.rs", 9));
+    }
+
+    #[test]
+    fn skips_keywords_inside_words_and_other_lines() {
+        assert_eq!(parse_line("a.rs:1:let MY_TODO = TODOS;"), None);
+        assert_eq!(parse_line("a.rs:1:naïveTODO"), None);
+        assert_eq!(parse_line("a.rs:x:// TODO"), None);
+        assert_eq!(parse_line("Binary file a.bin matches"), None);
+    }
+
+    #[test]
+    fn keeps_at_most_the_limit() {
+        let output = (1..=MAX_COMMENTS + 5).map(|line| format!("a.rs:{}:// TODO {}\n", line, line)).collect::<String>();
+        let comments = parse_output(&output);
+        assert_eq!(comments.len(), MAX_COMMENTS);
+        assert_eq!(comments[0].location(), "a.rs:1");
+    }
+}
@@ -0,0 +1,175 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::TodoItem;
+
+/// Version written by this build. Bump it together with a new step in `migrate` whenever the
+/// meaning of existing fields changes; purely additive fields don't need a new version.
+pub const SCHEMA_VERSION: u64 = 2;
+
+/// Name of the list that holds the items of files written before there were several lists.
+pub const DEFAULT_LIST: &str = "todo";
+
+/// The on-disk envelope around the todo items.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TodoFile {
+    pub version: u64,
+    pub next_id: usize,
+    pub next_display_order: usize,
+    #[serde(default)]
+    pub lists: Vec<ListMeta>,
+    pub items: Vec<TodoItem>,
+    // Fields added by newer builds, written back untouched
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// A named list and the settings that belong to it rather than to a plugin instance.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ListMeta {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sort_by_priority: Option<bool>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// Parses a todo file of any known version, upgrading it to the current schema.
+pub fn parse(data: &str) -> Result<TodoFile, String> {
+    let value: Value = serde_json::from_str(data).map_err(|error| error.to_string())?;
+    let value = migrate(value)?;
+    serde_json::from_value(value).map_err(|error| error.to_string())
+}
+
+fn migrate(mut value: Value) -> Result<Value, String> {
+    loop {
+        let version = match &value {
+            // Files written before the envelope existed are a bare array of items
+            Value::Array(_) => 0,
+            Value::Object(object) => object
+                .get("version")
+                .and_then(Value::as_u64)
+                .ok_or_else(|| "missing or invalid \"version\" field".to_string())?,
+            _ => return Err("expected an array or an object at the top level".to_string()),
+        };
+
+        value = match version {
+            SCHEMA_VERSION => return Ok(value),
+            0 => migrate_v0_to_v1(value),
+            1 => migrate_v1_to_v2(value),
+            newer if newer > SCHEMA_VERSION => {
+                return Err(format!(
+                    "written by a newer zellij-todo (schema v{}, this build supports up to v{}) - update the plugin",
+                    newer, SCHEMA_VERSION
+                ))
+            }
+            unknown => return Err(format!("unknown schema version {}", unknown)),
+        };
+    }
+}
+
+fn migrate_v0_to_v1(value: Value) -> Value {
+    let mut items = match value {
+        Value::Array(items) => items,
+        _ => Vec::new(),
+    };
+
+    let field = |item: &Value, name: &str| item.get(name).and_then(Value::as_u64).unwrap_or(0);
+
+    // The earliest files had no display_order; when no item has one, use the stored position
+    if items.iter().all(|item| field(item, "display_order") == 0) {
+        for (index, item) in items.iter_mut().enumerate() {
+            if let Value::Object(object) = item {
+                object.insert("display_order".to_string(), Value::from(index));
+            }
+        }
+    }
+
+    let next_id = items.iter().map(|item| field(item, "id") + 1).max().unwrap_or(0);
+    let next_display_order = items.iter().map(|item| field(item, "display_order") + 1).max().unwrap_or(0);
+
+    serde_json::json!({
+        "version": 1,
+        "next_id": next_id,
+        "next_display_order": next_display_order,
+        "meta": {},
+        "items": items,
+    })
+}
+
+fn migrate_v1_to_v2(value: Value) -> Value {
+    // v2 holds several named lists: the single list becomes the default one
+    let mut object = match value {
+        Value::Object(object) => object,
+        _ => Map::new(),
+    };
+
+    let mut meta = match object.remove("meta") {
+        Some(Value::Object(meta)) => meta,
+        _ => Map::new(),
+    };
+    meta.insert("name".to_string(), Value::from(DEFAULT_LIST));
+
+    if let Some(Value::Array(items)) = object.get_mut("items") {
+        for item in items {
+            if let Value::Object(item) = item {
+                item.insert("list".to_string(), Value::from(DEFAULT_LIST));
+            }
+        }
+    }
+
+    object.insert("version".to_string(), Value::from(2));
+    object.insert("lists".to_string(), Value::Array(vec![Value::Object(meta)]));
+    Value::Object(object)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrates_a_bare_array_from_v0() {
+        let file = parse(r#"[{"text": "a", "done": false, "id": 4}, {"text": "b", "done": true, "id": 7}]"#).unwrap();
+        assert_eq!(file.version, SCHEMA_VERSION);
+        assert_eq!(file.next_id, 8);
+        // Without any display_order the stored position is used
+        assert_eq!(file.items.iter().map(|item| item.display_order).collect::<Vec<_>>(), [0, 1]);
+        assert_eq!(file.next_display_order, 2);
+        assert_eq!(file.lists, [ListMeta { name: DEFAULT_LIST.to_string(), ..Default::default() }]);
+        assert!(file.items.iter().all(|item| item.list == DEFAULT_LIST));
+    }
+
+    #[test]
+    fn keeps_display_order_from_v0_when_present() {
+        let file = parse(r#"[{"text": "a", "done": true, "id": 0, "display_order": 0}, {"text": "b", "done": false, "id": 1, "display_order": 5}]"#).unwrap();
+        assert_eq!(file.items.iter().map(|item| item.display_order).collect::<Vec<_>>(), [0, 5]);
+        assert_eq!(file.next_display_order, 6);
+    }
+
+    #[test]
+    fn migrates_v1_meta_into_the_default_list() {
+        let file = parse(
+            r#"{"version": 1, "next_id": 3, "next_display_order": 9, "meta": {"sort_by_priority": true, "color": "red"},
+                "items": [{"text": "a", "done": false, "id": 2, "display_order": 8}], "theme": "dark"}"#,
+        )
+        .unwrap();
+        assert_eq!((file.next_id, file.next_display_order), (3, 9));
+        assert_eq!(file.lists.len(), 1);
+        assert_eq!(file.lists[0].name, DEFAULT_LIST);
+        assert_eq!(file.lists[0].sort_by_priority, Some(true));
+        assert_eq!(file.lists[0].extra.get("color"), Some(&Value::from("red")));
+        assert_eq!(file.items[0].list, DEFAULT_LIST);
+        // Unknown top-level fields are carried along
+        assert_eq!(file.extra.get("theme"), Some(&Value::from("dark")));
+        assert!(!file.extra.contains_key("meta"));
+    }
+
+    #[test]
+    fn rejects_newer_and_broken_files() {
+        let newer = parse(r#"{"version": 99, "next_id": 0, "next_display_order": 0, "items": []}"#).unwrap_err();
+        assert!(newer.contains("newer"), "{}", newer);
+        assert!(parse(r#"{"next_id": 0, "items": []}"#).unwrap_err().contains("version"));
+        assert!(parse("42").is_err());
+        assert!(parse("[{").is_err());
+    }
+}
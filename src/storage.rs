@@ -0,0 +1,63 @@
+use std::collections::hash_map::RandomState;
+use std::fs;
+use std::hash::BuildHasher;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Replaces the file at `path` with `data` without ever leaving a truncated file behind.
+pub fn write_atomic(path: &str, data: &str) -> io::Result<()> {
+    // Write next to the target and rename over it, so a crash or full disk leaves either the
+    // old or the new contents. RandomState is seeded from the OS, which gives every save its
+    // own temp file, so instances saving at the same time don't write into each other's
+    let temp_path = format!("{}.{:016x}.tmp", path, RandomState::new().hash_one(path));
+    let result = fs::File::create(&temp_path)
+        .and_then(|mut file| {
+            file.write_all(data.as_bytes())?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&temp_path, path));
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+pub fn backup_path(path: &str, generation: usize) -> String {
+    format!("{}.bak.{}", path, generation)
+}
+
+/// Copies the current file to `.bak.1`, shifting older backups up and dropping the oldest
+/// beyond `count`.
+pub fn rotate_backups(path: &str, count: usize) -> io::Result<()> {
+    if count == 0 || !Path::new(path).exists() {
+        return Ok(());
+    }
+
+    for generation in (1..count).rev() {
+        let older = backup_path(path, generation);
+        if Path::new(&older).exists() {
+            fs::rename(&older, backup_path(path, generation + 1))?;
+        }
+    }
+    fs::copy(path, backup_path(path, 1)).map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_through_a_temp_file_it_cleans_up() {
+        let dir = std::env::temp_dir().join(format!("zellij-todo-storage-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("todos.json").to_string_lossy().into_owned();
+
+        write_atomic(&path, "old").unwrap();
+        write_atomic(&path, "new").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
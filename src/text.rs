@@ -0,0 +1,209 @@
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// Terminal columns taken by one grapheme cluster. Combining marks add nothing, and emoji
+/// sequences joined into one glyph take two columns rather than the sum of their parts.
+pub fn grapheme_width(grapheme: &str) -> usize {
+    grapheme.width().min(2)
+}
+
+/// Terminal columns taken by plain text (no escape sequences).
+pub fn width(text: &str) -> usize {
+    text.graphemes(true).map(grapheme_width).sum()
+}
+
+/// Shortens plain text to at most `max_width` columns, ending in `…` when anything was cut.
+pub fn truncate(text: &str, max_width: usize) -> String {
+    if width(text) <= max_width {
+        return text.to_string();
+    }
+    if max_width == 0 {
+        return String::new();
+    }
+
+    let mut truncated = String::new();
+    let mut used = 0;
+    for grapheme in text.graphemes(true) {
+        let grapheme_width = grapheme_width(grapheme);
+        if used + grapheme_width > max_width - 1 {
+            break;
+        }
+        truncated.push_str(grapheme);
+        used += grapheme_width;
+    }
+    truncated.push('…');
+    truncated
+}
+
+/// Shortens plain text to at most `max_width` columns by cutting from the start, beginning
+/// with `…` when anything was cut.
+pub fn truncate_start(text: &str, max_width: usize) -> String {
+    if width(text) <= max_width {
+        return text.to_string();
+    }
+    if max_width == 0 {
+        return String::new();
+    }
+
+    let mut kept = Vec::new();
+    let mut used = 0;
+    for grapheme in text.graphemes(true).rev() {
+        let grapheme_width = grapheme_width(grapheme);
+        if used + grapheme_width > max_width - 1 {
+            break;
+        }
+        kept.push(grapheme);
+        used += grapheme_width;
+    }
+    kept.reverse();
+    format!("…{}", kept.concat())
+}
+
+/// Cuts a line that may contain escape sequences to `max_width` columns. Escape sequences past
+/// the cut are kept, so styles are still reset and nothing spills into the next line.
+pub fn clip(line: &str, max_width: usize) -> String {
+    let mut clipped = String::with_capacity(line.len());
+    let mut used = 0;
+    let mut rest = line;
+
+    while !rest.is_empty() {
+        if let Some(sequence_len) = escape_sequence_len(rest) {
+            clipped.push_str(&rest[..sequence_len]);
+            rest = &rest[sequence_len..];
+            continue;
+        }
+
+        // Control characters such as ESC always form a cluster of their own
+        let grapheme = rest.graphemes(true).next().unwrap_or(rest);
+        let grapheme_width = grapheme_width(grapheme);
+        if used + grapheme_width <= max_width {
+            clipped.push_str(grapheme);
+            used += grapheme_width;
+        } else {
+            // Anything after the cut that isn't an escape sequence is dropped
+            used = max_width;
+        }
+        rest = &rest[grapheme.len()..];
+    }
+
+    clipped
+}
+
+/// Length of the CSI (`ESC [ ... final`) or OSC (`ESC ] ... BEL`) sequence at the start of
+/// `text`, if there is one.
+fn escape_sequence_len(text: &str) -> Option<usize> {
+    let bytes = text.as_bytes();
+    if bytes.first() != Some(&0x1b) {
+        return None;
+    }
+    match bytes.get(1) {
+        Some(b'[') => bytes[2..]
+            .iter()
+            .position(|byte| (0x40..=0x7e).contains(byte))
+            .map(|end| end + 3),
+        Some(b']') => bytes[2..].iter().position(|&byte| byte == 0x07).map(|end| end + 3),
+        Some(_) => Some(2),
+        None => Some(1),
+    }
+}
+
+/// Breaks plain text into lines of at most `max_width` columns, at spaces where possible.
+/// Words longer than a whole line are split wherever they hit the edge. Always returns at least
+/// one line.
+pub fn wrap(text: &str, max_width: usize) -> Vec<String> {
+    if max_width == 0 {
+        return vec![String::new()];
+    }
+
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    let mut used = 0;
+
+    for word in runs(text) {
+        let word_width = width(word);
+        if word.trim().is_empty() {
+            // Spaces at the edge are where we break; they don't carry over to the next line
+            if used + word_width <= max_width {
+                line.push_str(word);
+                used += word_width;
+            } else if used > 0 {
+                lines.push(std::mem::take(&mut line).trim_end().to_string());
+                used = 0;
+            }
+            continue;
+        }
+
+        if used + word_width > max_width && used > 0 {
+            lines.push(std::mem::take(&mut line).trim_end().to_string());
+            used = 0;
+        }
+        for grapheme in word.graphemes(true) {
+            let grapheme_width = grapheme_width(grapheme);
+            if used + grapheme_width > max_width && used > 0 {
+                lines.push(std::mem::take(&mut line));
+                used = 0;
+            }
+            line.push_str(grapheme);
+            used += grapheme_width;
+        }
+    }
+
+    if !line.is_empty() || lines.is_empty() {
+        lines.push(line.trim_end().to_string());
+    }
+    lines
+}
+
+/// Splits text into alternating runs of whitespace and non-whitespace.
+fn runs(text: &str) -> impl Iterator<Item = &str> {
+    let mut rest = text;
+    std::iter::from_fn(move || {
+        let first = rest.chars().next()?;
+        let end = rest
+            .find(|c: char| c.is_whitespace() != first.is_whitespace())
+            .unwrap_or(rest.len());
+        let (run, tail) = rest.split_at(end);
+        rest = tail;
+        Some(run)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn measures_display_width() {
+        assert_eq!(width("abc"), 3);
+        assert_eq!(width("日本"), 4);
+        assert_eq!(width("e\u{301}"), 1);
+        assert_eq!(width("👨‍👩‍👧"), 2);
+    }
+
+    #[test]
+    fn truncates_at_either_end() {
+        assert_eq!(truncate("hello", 5), "hello");
+        assert_eq!(truncate("hello world", 6), "hello…");
+        // A wide character that doesn't fit whole is left out
+        assert_eq!(truncate("ab日本", 4), "ab…");
+        assert_eq!(truncate("hello", 0), "");
+        assert_eq!(truncate_start("hello world", 6), "…world");
+        assert_eq!(truncate_start("日本語", 4), "…語");
+    }
+
+    #[test]
+    fn clips_around_escape_sequences() {
+        assert_eq!(clip("\x1b[31mhello\x1b[0m", 3), "\x1b[31mhel\x1b[0m");
+        assert_eq!(clip("日本語", 5), "日本");
+        assert_eq!(clip("\x1b]0;title\x07ab", 1), "\x1b]0;title\x07a");
+    }
+
+    #[test]
+    fn wraps_at_spaces_and_splits_long_words() {
+        assert_eq!(wrap("buy some milk", 8), ["buy some", "milk"]);
+        assert_eq!(wrap("abcdefghij", 4), ["abcd", "efgh", "ij"]);
+        assert_eq!(wrap("日本語です", 5), ["日本", "語で", "す"]);
+        assert_eq!(wrap("", 10), [""]);
+        assert_eq!(wrap("text", 0), [""]);
+    }
+}